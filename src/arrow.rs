@@ -0,0 +1,724 @@
+// Columnar export of a walk through the Arrow C Data Interface
+// (`to_arrow`, behind the `arrow` cargo feature).
+//
+// One depth-first pass over `_fields` (not `walk_dfs`'s storage order:
+// the `field` and `index` columns need the names) fills a column per
+// attribute; the finished table is handed to Python via the Arrow
+// PyCapsule protocol (`__arrow_c_array__` and `__arrow_c_stream__`),
+// which pyarrow, Polars, DuckDB and other Arrow consumers import without
+// a copy. The C structs are declared here from the specification, so
+// building needs neither the arrow crates nor pyarrow.
+//
+// https://arrow.apache.org/docs/format/CDataInterface.html
+// https://arrow.apache.org/docs/format/CDataInterface/PyCapsuleInterface.html
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::ffi::{CStr, c_char, c_int, c_void};
+use std::hash::BuildHasherDefault;
+use std::sync::Arc;
+
+use pyo3::ffi::{self, PyObject, PyTypeObject};
+use pyo3::intern;
+use pyo3::prelude::*;
+use pyo3::types::{PyList, PyString, PyTuple};
+
+use crate::fields::NodeFields;
+use crate::{PtrHasher, Walker};
+
+/// `struct ArrowSchema` from the C Data Interface.
+#[repr(C)]
+struct ArrowSchema {
+    format: *const c_char,
+    name: *const c_char,
+    metadata: *const c_char,
+    flags: i64,
+    n_children: i64,
+    children: *mut *mut ArrowSchema,
+    dictionary: *mut ArrowSchema,
+    release: Option<unsafe extern "C" fn(*mut ArrowSchema)>,
+    private_data: *mut c_void,
+}
+
+/// `struct ArrowArray` from the C Data Interface.
+#[repr(C)]
+struct ArrowArray {
+    length: i64,
+    null_count: i64,
+    offset: i64,
+    n_buffers: i64,
+    n_children: i64,
+    buffers: *mut *const c_void,
+    children: *mut *mut ArrowArray,
+    dictionary: *mut ArrowArray,
+    release: Option<unsafe extern "C" fn(*mut ArrowArray)>,
+    private_data: *mut c_void,
+}
+
+/// `struct ArrowArrayStream` from the C Stream Interface.
+#[repr(C)]
+struct ArrowArrayStream {
+    get_schema: Option<unsafe extern "C" fn(*mut ArrowArrayStream, *mut ArrowSchema) -> c_int>,
+    get_next: Option<unsafe extern "C" fn(*mut ArrowArrayStream, *mut ArrowArray) -> c_int>,
+    get_last_error: Option<unsafe extern "C" fn(*mut ArrowArrayStream) -> *const c_char>,
+    release: Option<unsafe extern "C" fn(*mut ArrowArrayStream)>,
+    private_data: *mut c_void,
+}
+
+const ARROW_FLAG_NULLABLE: i64 = 2;
+
+/// Value storage of one column, in Arrow's physical layout.
+enum Values {
+    /// Format `l`.
+    Int64(Vec<i64>),
+    /// Format `i`.
+    Int32(Vec<i32>),
+    /// Format `U` (large string): `len + 1` offsets into `data`, so
+    /// there is no 2 GiB limit on the total text.
+    LargeUtf8 { offsets: Vec<i64>, data: Vec<u8> },
+}
+
+/// One nullable column: its values plus a validity bitmap (bit `i` set
+/// when row `i` is non-null).
+struct Column {
+    name: &'static CStr,
+    values: Values,
+    validity: Vec<u8>,
+    null_count: usize,
+    len: usize,
+}
+
+impl Column {
+    fn new(name: &'static CStr, values: Values) -> Self {
+        Self {
+            name,
+            values,
+            validity: Vec::new(),
+            null_count: 0,
+            len: 0,
+        }
+    }
+
+    fn int64(name: &'static CStr) -> Self {
+        Self::new(name, Values::Int64(Vec::new()))
+    }
+
+    fn int32(name: &'static CStr) -> Self {
+        Self::new(name, Values::Int32(Vec::new()))
+    }
+
+    fn large_utf8(name: &'static CStr) -> Self {
+        let values = Values::LargeUtf8 {
+            offsets: vec![0],
+            data: Vec::new(),
+        };
+        Self::new(name, values)
+    }
+
+    fn format(&self) -> &'static CStr {
+        match self.values {
+            Values::Int64(_) => c"l",
+            Values::Int32(_) => c"i",
+            Values::LargeUtf8 { .. } => c"U",
+        }
+    }
+
+    fn push_validity(&mut self, valid: bool) {
+        if self.len.is_multiple_of(8) {
+            self.validity.push(0);
+        }
+        if valid {
+            self.validity[self.len / 8] |= 1 << (self.len % 8);
+        } else {
+            self.null_count += 1;
+        }
+        self.len += 1;
+    }
+
+    fn push_i64(&mut self, value: Option<i64>) {
+        let Values::Int64(data) = &mut self.values else {
+            unreachable!("{:?} is not an int64 column", self.name);
+        };
+        data.push(value.unwrap_or_default());
+        self.push_validity(value.is_some());
+    }
+
+    fn push_i32(&mut self, value: Option<i32>) {
+        let Values::Int32(data) = &mut self.values else {
+            unreachable!("{:?} is not an int32 column", self.name);
+        };
+        data.push(value.unwrap_or_default());
+        self.push_validity(value.is_some());
+    }
+
+    fn push_str(&mut self, value: Option<&str>) {
+        let Values::LargeUtf8 { offsets, data } = &mut self.values else {
+            unreachable!("{:?} is not a string column", self.name);
+        };
+        data.extend_from_slice(value.unwrap_or_default().as_bytes());
+        offsets.push(data.len() as i64);
+        self.push_validity(value.is_some());
+    }
+
+    /// Buffer pointers in the order the column's format prescribes. The
+    /// validity bitmap may be null when there are no nulls.
+    fn buffers(&self) -> Box<[*const c_void]> {
+        let validity = if self.null_count == 0 {
+            std::ptr::null()
+        } else {
+            self.validity.as_ptr().cast()
+        };
+        match &self.values {
+            Values::Int64(data) => Box::new([validity, data.as_ptr().cast()]),
+            Values::Int32(data) => Box::new([validity, data.as_ptr().cast()]),
+            Values::LargeUtf8 { offsets, data } => {
+                Box::new([validity, offsets.as_ptr().cast(), data.as_ptr().cast()])
+            }
+        }
+    }
+}
+
+/// Columns of the table `to_arrow` builds, one row per node in
+/// depth-first pre-order.
+struct Builder {
+    node_id: Column,
+    parent_id: Column,
+    type_name: Column,
+    field: Column,
+    index: Column,
+    lineno: Column,
+    col_offset: Column,
+    end_lineno: Column,
+    end_col_offset: Column,
+    value: Column,
+}
+
+impl Builder {
+    fn new() -> Self {
+        Self {
+            node_id: Column::int64(c"node_id"),
+            parent_id: Column::int64(c"parent_id"),
+            type_name: Column::large_utf8(c"type"),
+            field: Column::large_utf8(c"field"),
+            index: Column::int32(c"index"),
+            lineno: Column::int32(c"lineno"),
+            col_offset: Column::int32(c"col_offset"),
+            end_lineno: Column::int32(c"end_lineno"),
+            end_col_offset: Column::int32(c"end_col_offset"),
+            value: Column::large_utf8(c"value"),
+        }
+    }
+
+    fn into_columns(self) -> Vec<Column> {
+        vec![
+            self.node_id,
+            self.parent_id,
+            self.type_name,
+            self.field,
+            self.index,
+            self.lineno,
+            self.col_offset,
+            self.end_lineno,
+            self.end_col_offset,
+            self.value,
+        ]
+    }
+}
+
+/// A node waiting on the traversal stack, with where it hangs in its
+/// parent.
+struct Pending<'py> {
+    node: Bound<'py, PyAny>,
+    parent: Option<i64>,
+    field: Option<Bound<'py, PyString>>,
+    index: Option<i32>,
+}
+
+/// `node.<name>` as an `int32`, or `None` if it is missing, not an
+/// integer, or out of range.
+fn int_field<'py>(
+    fields: &NodeFields<'py>,
+    node: &Bound<'py, PyAny>,
+    name: &Bound<'py, PyString>,
+) -> PyResult<Option<i32>> {
+    Ok(fields
+        .get(node, name)?
+        .and_then(|value| value.extract().ok()))
+}
+
+/// Walk `roots` in depth-first pre-order and fill a [`Builder`].
+fn build(walker: &mut Walker<'_, '_>, roots: &[*mut PyObject]) -> PyResult<Builder> {
+    let py = walker.py;
+    let constant_type = py.import("ast")?.getattr("Constant")?;
+    let mut fields = NodeFields::new(py);
+    let mut type_names: HashMap<*mut PyTypeObject, String, BuildHasherDefault<PtrHasher>> =
+        HashMap::default();
+    let mut builder = Builder::new();
+    let mut stack: Vec<Pending<'_>> = roots
+        .iter()
+        .rev()
+        // SAFETY: the roots are alive for the whole call.
+        .map(|&root| Pending {
+            node: unsafe { Bound::from_borrowed_ptr(py, root) },
+            parent: None,
+            field: None,
+            index: None,
+        })
+        .collect();
+    let mut children = Vec::new();
+
+    while let Some(Pending {
+        node,
+        parent,
+        field,
+        index,
+    }) = stack.pop()
+    {
+        let id = builder.node_id.len as i64;
+        walker.check_budget(builder.node_id.len + 1)?;
+        let type_name = match type_names.entry(node.get_type_ptr()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(node.get_type().name()?.to_string()),
+        };
+        builder.node_id.push_i64(Some(id));
+        builder.parent_id.push_i64(parent);
+        builder.type_name.push_str(Some(type_name));
+        builder
+            .field
+            .push_str(field.as_ref().map(|f| f.to_str()).transpose()?);
+        builder.index.push_i32(index);
+
+        if !walker.is_root(&node) {
+            // A non-AST root of a `strict=False` call: a bare leaf.
+            for column in [
+                &mut builder.lineno,
+                &mut builder.col_offset,
+                &mut builder.end_lineno,
+                &mut builder.end_col_offset,
+            ] {
+                column.push_i32(None);
+            }
+            builder.value.push_str(None);
+            continue;
+        }
+        builder
+            .lineno
+            .push_i32(int_field(&fields, &node, intern!(py, "lineno"))?);
+        builder
+            .col_offset
+            .push_i32(int_field(&fields, &node, intern!(py, "col_offset"))?);
+        builder
+            .end_lineno
+            .push_i32(int_field(&fields, &node, intern!(py, "end_lineno"))?);
+        builder
+            .end_col_offset
+            .push_i32(int_field(&fields, &node, intern!(py, "end_col_offset"))?);
+        let value = if node.is_instance(&constant_type)? {
+            match fields.get(&node, intern!(py, "value"))? {
+                Some(value) => Some(value.repr()?),
+                None => None,
+            }
+        } else {
+            None
+        };
+        builder
+            .value
+            .push_str(value.as_ref().map(|v| v.to_str()).transpose()?);
+
+        for name in fields.names(&node)?.iter() {
+            let Some(value) = fields.get(&node, name)? else {
+                continue;
+            };
+            if let Ok(list) = value.cast::<PyList>() {
+                for (i, item) in list.iter().enumerate() {
+                    if walker.is_ast(&item) {
+                        children.push(Pending {
+                            node: item,
+                            parent: Some(id),
+                            field: Some(name.clone()),
+                            index: Some(i as i32),
+                        });
+                    }
+                }
+            } else if walker.is_ast(&value) {
+                children.push(Pending {
+                    node: value,
+                    parent: Some(id),
+                    field: Some(name.clone()),
+                    index: None,
+                });
+            }
+        }
+        stack.extend(children.drain(..).rev());
+    }
+
+    Ok(builder)
+}
+
+/// Result of `to_arrow`: a node table exported through the Arrow
+/// PyCapsule interface, e.g. `pyarrow.record_batch(table)` or
+/// `polars.DataFrame(table)`.
+#[pyclass(module = "fast_walk", frozen)]
+pub struct NodeTable {
+    columns: Arc<Vec<Column>>,
+    len: usize,
+}
+
+pub(crate) fn to_arrow(
+    walker: &mut Walker<'_, '_>,
+    roots: &[*mut PyObject],
+) -> PyResult<NodeTable> {
+    let builder = build(walker, roots)?;
+    let len = builder.node_id.len;
+    Ok(NodeTable {
+        columns: Arc::new(builder.into_columns()),
+        len,
+    })
+}
+
+#[pymethods]
+impl NodeTable {
+    fn __len__(&self) -> usize {
+        self.len
+    }
+
+    /// Export the schema, a struct with one child per column, as an
+    /// `arrow_schema` capsule.
+    fn __arrow_c_schema__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        schema_capsule(py, export_schema(&self.columns))
+    }
+
+    /// Export the table as a struct array: a pair of `arrow_schema` and
+    /// `arrow_array` capsules. The schema is fixed, so
+    /// `requested_schema` is accepted and ignored, which the protocol
+    /// allows.
+    #[pyo3(signature = (requested_schema = None))]
+    fn __arrow_c_array__<'py>(
+        &self,
+        py: Python<'py>,
+        requested_schema: Option<Bound<'py, PyAny>>,
+    ) -> PyResult<Bound<'py, PyTuple>> {
+        let _ = requested_schema;
+        let schema = schema_capsule(py, export_schema(&self.columns))?;
+        let array = array_capsule(py, export_array(&self.columns, self.len))?;
+        PyTuple::new(py, [schema, array])
+    }
+
+    /// Export the table as a stream of one record batch, in an
+    /// `arrow_array_stream` capsule — the form DuckDB and Polars import.
+    /// `requested_schema` is ignored, as in `__arrow_c_array__`.
+    #[pyo3(signature = (requested_schema = None))]
+    fn __arrow_c_stream__<'py>(
+        &self,
+        py: Python<'py>,
+        requested_schema: Option<Bound<'py, PyAny>>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let _ = requested_schema;
+        stream_capsule(py, export_stream(&self.columns, self.len))
+    }
+}
+
+/// Owned by a released-on-demand [`ArrowSchema`]: the children it
+/// points to.
+struct SchemaPrivate {
+    children: Box<[*mut ArrowSchema]>,
+}
+
+/// Owned by a released-on-demand [`ArrowArray`]: its buffer pointer
+/// list and children, plus a reference keeping the column data alive.
+struct ArrayPrivate {
+    _columns: Arc<Vec<Column>>,
+    buffers: Box<[*const c_void]>,
+    children: Box<[*mut ArrowArray]>,
+}
+
+fn schema_node(
+    format: &'static CStr,
+    name: &'static CStr,
+    flags: i64,
+    children: Box<[*mut ArrowSchema]>,
+) -> ArrowSchema {
+    let n_children = children.len() as i64;
+    let private = Box::into_raw(Box::new(SchemaPrivate { children }));
+    ArrowSchema {
+        format: format.as_ptr(),
+        name: name.as_ptr(),
+        metadata: std::ptr::null(),
+        flags,
+        n_children,
+        children: unsafe { (*private).children.as_mut_ptr() },
+        dictionary: std::ptr::null_mut(),
+        release: Some(release_schema),
+        private_data: private.cast(),
+    }
+}
+
+fn export_schema(columns: &[Column]) -> ArrowSchema {
+    let children = columns
+        .iter()
+        .map(|column| {
+            let child = schema_node(
+                column.format(),
+                column.name,
+                ARROW_FLAG_NULLABLE,
+                Box::new([]),
+            );
+            Box::into_raw(Box::new(child))
+        })
+        .collect();
+    schema_node(c"+s", c"", 0, children)
+}
+
+fn array_node(
+    columns: &Arc<Vec<Column>>,
+    length: usize,
+    null_count: usize,
+    buffers: Box<[*const c_void]>,
+    children: Box<[*mut ArrowArray]>,
+) -> ArrowArray {
+    let n_buffers = buffers.len() as i64;
+    let n_children = children.len() as i64;
+    let private = Box::into_raw(Box::new(ArrayPrivate {
+        _columns: Arc::clone(columns),
+        buffers,
+        children,
+    }));
+    ArrowArray {
+        length: length as i64,
+        null_count: null_count as i64,
+        offset: 0,
+        n_buffers,
+        n_children,
+        buffers: unsafe { (*private).buffers.as_mut_ptr() },
+        children: unsafe { (*private).children.as_mut_ptr() },
+        dictionary: std::ptr::null_mut(),
+        release: Some(release_array),
+        private_data: private.cast(),
+    }
+}
+
+fn export_array(columns: &Arc<Vec<Column>>, len: usize) -> ArrowArray {
+    let children = columns
+        .iter()
+        .map(|column| {
+            let child = array_node(
+                columns,
+                column.len,
+                column.null_count,
+                column.buffers(),
+                Box::new([]),
+            );
+            Box::into_raw(Box::new(child))
+        })
+        .collect();
+    // A struct array has a single (validity) buffer; no row is null.
+    array_node(columns, len, 0, Box::new([std::ptr::null()]), children)
+}
+
+/// `release` callback of every exported schema. Releases the children
+/// still owned by this node (a consumer may have moved some out, which
+/// marks them released), frees their structs, then its own private data.
+unsafe extern "C" fn release_schema(schema: *mut ArrowSchema) {
+    unsafe {
+        let private = Box::from_raw((*schema).private_data.cast::<SchemaPrivate>());
+        for &child in private.children.iter() {
+            if let Some(release) = (*child).release {
+                release(child);
+            }
+            drop(Box::from_raw(child));
+        }
+        (*schema).private_data = std::ptr::null_mut();
+        (*schema).release = None;
+    }
+}
+
+/// `release` callback of every exported array; see [`release_schema`].
+unsafe extern "C" fn release_array(array: *mut ArrowArray) {
+    unsafe {
+        let private = Box::from_raw((*array).private_data.cast::<ArrayPrivate>());
+        for &child in private.children.iter() {
+            if let Some(release) = (*child).release {
+                release(child);
+            }
+            drop(Box::from_raw(child));
+        }
+        (*array).private_data = std::ptr::null_mut();
+        (*array).release = None;
+    }
+}
+
+/// Owned by an [`ArrowArrayStream`]: the table it yields, and whether
+/// that single batch has been handed out yet.
+struct StreamPrivate {
+    columns: Arc<Vec<Column>>,
+    len: usize,
+    done: bool,
+}
+
+fn export_stream(columns: &Arc<Vec<Column>>, len: usize) -> ArrowArrayStream {
+    let private = Box::new(StreamPrivate {
+        columns: Arc::clone(columns),
+        len,
+        done: false,
+    });
+    ArrowArrayStream {
+        get_schema: Some(stream_get_schema),
+        get_next: Some(stream_get_next),
+        get_last_error: Some(stream_get_last_error),
+        release: Some(release_stream),
+        private_data: Box::into_raw(private).cast(),
+    }
+}
+
+unsafe extern "C" fn stream_get_schema(
+    stream: *mut ArrowArrayStream,
+    out: *mut ArrowSchema,
+) -> c_int {
+    unsafe {
+        let private = &*(*stream).private_data.cast::<StreamPrivate>();
+        out.write(export_schema(&private.columns));
+    }
+    0
+}
+
+/// Yield the table once, then the released array that marks the end of
+/// the stream.
+unsafe extern "C" fn stream_get_next(stream: *mut ArrowArrayStream, out: *mut ArrowArray) -> c_int {
+    unsafe {
+        let private = &mut *(*stream).private_data.cast::<StreamPrivate>();
+        if private.done {
+            out.write(ArrowArray {
+                length: 0,
+                null_count: 0,
+                offset: 0,
+                n_buffers: 0,
+                n_children: 0,
+                buffers: std::ptr::null_mut(),
+                children: std::ptr::null_mut(),
+                dictionary: std::ptr::null_mut(),
+                release: None,
+                private_data: std::ptr::null_mut(),
+            });
+        } else {
+            out.write(export_array(&private.columns, private.len));
+            private.done = true;
+        }
+    }
+    0
+}
+
+/// Neither callback above can fail.
+unsafe extern "C" fn stream_get_last_error(_stream: *mut ArrowArrayStream) -> *const c_char {
+    std::ptr::null()
+}
+
+unsafe extern "C" fn release_stream(stream: *mut ArrowArrayStream) {
+    unsafe {
+        drop(Box::from_raw(
+            (*stream).private_data.cast::<StreamPrivate>(),
+        ));
+        (*stream).private_data = std::ptr::null_mut();
+        (*stream).release = None;
+    }
+}
+
+const SCHEMA_CAPSULE: &CStr = c"arrow_schema";
+const ARRAY_CAPSULE: &CStr = c"arrow_array";
+const STREAM_CAPSULE: &CStr = c"arrow_array_stream";
+
+/// Capsule destructor: release the struct unless a consumer already
+/// moved it out (which nulls `release`), then free it.
+unsafe extern "C" fn drop_schema_capsule(capsule: *mut PyObject) {
+    unsafe {
+        let schema =
+            ffi::PyCapsule_GetPointer(capsule, SCHEMA_CAPSULE.as_ptr()).cast::<ArrowSchema>();
+        if schema.is_null() {
+            ffi::PyErr_Clear();
+            return;
+        }
+        if let Some(release) = (*schema).release {
+            release(schema);
+        }
+        drop(Box::from_raw(schema));
+    }
+}
+
+unsafe extern "C" fn drop_array_capsule(capsule: *mut PyObject) {
+    unsafe {
+        let array = ffi::PyCapsule_GetPointer(capsule, ARRAY_CAPSULE.as_ptr()).cast::<ArrowArray>();
+        if array.is_null() {
+            ffi::PyErr_Clear();
+            return;
+        }
+        if let Some(release) = (*array).release {
+            release(array);
+        }
+        drop(Box::from_raw(array));
+    }
+}
+
+fn schema_capsule(py: Python<'_>, schema: ArrowSchema) -> PyResult<Bound<'_, PyAny>> {
+    let schema = Box::into_raw(Box::new(schema));
+    unsafe {
+        let capsule = ffi::PyCapsule_New(
+            schema.cast(),
+            SCHEMA_CAPSULE.as_ptr(),
+            Some(drop_schema_capsule),
+        );
+        if capsule.is_null() {
+            release_schema(schema);
+            drop(Box::from_raw(schema));
+            return Err(PyErr::fetch(py));
+        }
+        Ok(Bound::from_owned_ptr(py, capsule))
+    }
+}
+
+fn array_capsule(py: Python<'_>, array: ArrowArray) -> PyResult<Bound<'_, PyAny>> {
+    let array = Box::into_raw(Box::new(array));
+    unsafe {
+        let capsule = ffi::PyCapsule_New(
+            array.cast(),
+            ARRAY_CAPSULE.as_ptr(),
+            Some(drop_array_capsule),
+        );
+        if capsule.is_null() {
+            release_array(array);
+            drop(Box::from_raw(array));
+            return Err(PyErr::fetch(py));
+        }
+        Ok(Bound::from_owned_ptr(py, capsule))
+    }
+}
+
+unsafe extern "C" fn drop_stream_capsule(capsule: *mut PyObject) {
+    unsafe {
+        let stream =
+            ffi::PyCapsule_GetPointer(capsule, STREAM_CAPSULE.as_ptr()).cast::<ArrowArrayStream>();
+        if stream.is_null() {
+            ffi::PyErr_Clear();
+            return;
+        }
+        if let Some(release) = (*stream).release {
+            release(stream);
+        }
+        drop(Box::from_raw(stream));
+    }
+}
+
+fn stream_capsule(py: Python<'_>, stream: ArrowArrayStream) -> PyResult<Bound<'_, PyAny>> {
+    let stream = Box::into_raw(Box::new(stream));
+    unsafe {
+        let capsule = ffi::PyCapsule_New(
+            stream.cast(),
+            STREAM_CAPSULE.as_ptr(),
+            Some(drop_stream_capsule),
+        );
+        if capsule.is_null() {
+            release_stream(stream);
+            drop(Box::from_raw(stream));
+            return Err(PyErr::fetch(py));
+        }
+        Ok(Bound::from_owned_ptr(py, capsule))
+    }
+}
@@ -0,0 +1,240 @@
+// `ast.dump`, reimplemented for `dump`.
+//
+// Mirrors the stdlib's `_format` closely enough to be byte-identical:
+// fields come from `_fields` with `getattr` semantics, unset fields and
+// fields equal to a `None` class default switch to keyword form, and
+// (unless `show_empty`) `None` / `[]` values are held back and only
+// written if a later positional argument needs them to keep its place.
+//
+// The stdlib builds a string per node and joins them; here everything is
+// streamed into one buffer. A node's arguments are always written in the
+// multi-line layout first; the rare node that turns out to qualify for
+// the single-line form (at most three simple arguments) has its tail
+// rewritten. Without `indent` the two layouts coincide.
+//
+// Fields are read by name through `NodeFields`, not through the walk's
+// `FieldTable`: the table only records how many `_fields` a type has,
+// for reading that many values off the instance dict in storage order.
+// `dump` needs the names themselves, in `_fields` order (a node built
+// with keyword arguments stores them in argument order), plus
+// `_attributes`, and `NodeFields` reads those once per type per call.
+
+use std::collections::HashMap;
+use std::hash::BuildHasherDefault;
+
+use pyo3::exceptions::PyTypeError;
+use pyo3::ffi::PyTypeObject;
+use pyo3::intern;
+use pyo3::prelude::*;
+use pyo3::types::{PyList, PyString};
+
+use crate::fields::NodeFields;
+use crate::{PtrHasher, RecursionGuard, Walker, str_from_utf8, utf8};
+
+pub(crate) struct DumpOptions<'py> {
+    pub(crate) annotate_fields: bool,
+    pub(crate) include_attributes: bool,
+    /// Indentation per level; `None` for the single-line form.
+    pub(crate) indent: Option<Bound<'py, PyString>>,
+    pub(crate) show_empty: bool,
+}
+
+pub(crate) struct Dumper<'w, 'py> {
+    py: Python<'py>,
+    walker: &'w Walker<'w, 'py>,
+    fields: NodeFields<'py>,
+    options: DumpOptions<'py>,
+    indent: Vec<u8>,
+    /// `isinstance(node, (Constant, MatchSingleton))` by node type:
+    /// `None` is those nodes' value, not an empty field.
+    keeps_none: HashMap<*mut PyTypeObject, bool, BuildHasherDefault<PtrHasher>>,
+    out: Vec<u8>,
+}
+
+impl<'w, 'py> Dumper<'w, 'py> {
+    pub(crate) fn new(walker: &'w Walker<'w, 'py>, options: DumpOptions<'py>) -> PyResult<Self> {
+        let indent = match &options.indent {
+            Some(indent) => utf8(indent)?.into_owned(),
+            None => Vec::new(),
+        };
+        Ok(Self {
+            py: walker.py,
+            walker,
+            fields: NodeFields::new(walker.py),
+            options,
+            indent,
+            keeps_none: HashMap::default(),
+            out: Vec::new(),
+        })
+    }
+
+    pub(crate) fn dump(mut self, node: &Bound<'py, PyAny>) -> PyResult<Bound<'py, PyString>> {
+        if !self.walker.is_root(node) {
+            return Err(PyTypeError::new_err(format!(
+                "expected AST, got '{}'",
+                node.get_type().name()?
+            )));
+        }
+        self.node(node, 0)?;
+        str_from_utf8(self.py, &self.out)
+    }
+
+    /// Write `prefix` before the first argument or item, `sep` before
+    /// the others.
+    fn separator(&mut self, first: bool, level: usize) {
+        if self.options.indent.is_none() {
+            if !first {
+                self.out.extend_from_slice(b", ");
+            }
+            return;
+        }
+        if !first {
+            self.out.push(b',');
+        }
+        self.out.push(b'\n');
+        for _ in 0..level {
+            self.out.extend_from_slice(&self.indent);
+        }
+    }
+
+    fn repr(&mut self, value: &Bound<'py, PyAny>) -> PyResult<()> {
+        self.out.extend_from_slice(&utf8(&value.repr()?)?);
+        Ok(())
+    }
+
+    /// `_format(value, level)`; returns whether the result is "simple".
+    fn value(&mut self, value: &Bound<'py, PyAny>, level: usize) -> PyResult<bool> {
+        if self.walker.is_root(value) {
+            return self.node(value, level);
+        }
+        let Ok(list) = value.cast::<PyList>() else {
+            self.repr(value)?;
+            return Ok(true);
+        };
+        if list.is_empty() {
+            self.out.extend_from_slice(b"[]");
+            return Ok(true);
+        }
+        let _guard = RecursionGuard::enter(self.py)?;
+        let level = level + usize::from(self.options.indent.is_some());
+        self.out.push(b'[');
+        for (i, item) in list.iter().enumerate() {
+            self.separator(i == 0, level);
+            self.value(&item, level)?;
+        }
+        self.out.push(b']');
+        Ok(false)
+    }
+
+    fn keeps_none(&mut self, node: &Bound<'py, PyAny>) -> PyResult<bool> {
+        let py = self.py;
+        let key = node.get_type_ptr();
+        if let Some(&keeps_none) = self.keeps_none.get(&key) {
+            return Ok(keeps_none);
+        }
+        let ast = py.import(intern!(py, "ast"))?;
+        let keeps_none = node.is_instance(&ast.getattr(intern!(py, "Constant"))?)?
+            || node.is_instance(&ast.getattr(intern!(py, "MatchSingleton"))?)?;
+        self.keeps_none.insert(key, keeps_none);
+        Ok(keeps_none)
+    }
+
+    /// `value == []`, without a Python-level comparison for the usual
+    /// field values.
+    fn is_empty_list(&self, value: &Bound<'py, PyAny>) -> PyResult<bool> {
+        if let Ok(list) = value.cast_exact::<PyList>() {
+            return Ok(list.is_empty());
+        }
+        if value.is_exact_instance_of::<PyString>() {
+            return Ok(false);
+        }
+        value.eq(PyList::empty(self.py))
+    }
+
+    fn node(&mut self, node: &Bound<'py, PyAny>, level: usize) -> PyResult<bool> {
+        let _guard = RecursionGuard::enter(self.py)?;
+        let level = level + usize::from(self.options.indent.is_some());
+        let names = self.fields.names(node)?;
+        let attributes = if self.options.include_attributes {
+            self.fields.attributes(node)?
+        } else {
+            Default::default()
+        };
+        let keeps_none = self.keeps_none(node)?;
+        let class = node.get_type();
+        // `value is None and getattr(cls, name, ...) is None`
+        let is_none_default = |value: &Bound<'py, PyAny>, name: &Bound<'py, PyString>| {
+            value.is_none() && class.getattr(name).is_ok_and(|default| default.is_none())
+        };
+
+        self.out.extend_from_slice(&utf8(&class.name()?)?);
+        self.out.push(b'(');
+        let start = self.out.len();
+        // Where each argument's text starts and ends, in case the node
+        // turns out to fit on one line.
+        let mut spans = Vec::new();
+        let mut all_simple = true;
+        let mut keywords = self.options.annotate_fields;
+        let mut held_back: Vec<Vec<u8>> = Vec::new();
+
+        for name in names.iter() {
+            let Some(value) = self.fields.get(node, name)? else {
+                keywords = true;
+                continue;
+            };
+            if is_none_default(&value, name) {
+                keywords = true;
+                continue;
+            }
+            if !self.options.show_empty
+                && !keeps_none
+                && (value.is_none() || self.is_empty_list(&value)?)
+            {
+                held_back.push(utf8(&value.repr()?)?.into_owned());
+                continue;
+            }
+            if !keywords {
+                for text in held_back.drain(..) {
+                    self.separator(spans.is_empty(), level);
+                    let arg_start = self.out.len();
+                    self.out.extend_from_slice(&text);
+                    spans.push((arg_start, self.out.len()));
+                }
+            }
+            self.separator(spans.is_empty(), level);
+            let arg_start = self.out.len();
+            if keywords {
+                self.out.extend_from_slice(&utf8(name)?);
+                self.out.push(b'=');
+            }
+            all_simple &= self.value(&value, level)?;
+            spans.push((arg_start, self.out.len()));
+        }
+        for name in attributes.iter() {
+            let Some(value) = self.fields.get(node, name)? else {
+                continue;
+            };
+            if is_none_default(&value, name) {
+                continue;
+            }
+            self.separator(spans.is_empty(), level);
+            let arg_start = self.out.len();
+            self.out.extend_from_slice(&utf8(name)?);
+            self.out.push(b'=');
+            all_simple &= self.value(&value, level)?;
+            spans.push((arg_start, self.out.len()));
+        }
+
+        let one_line = all_simple && spans.len() <= 3;
+        if one_line && self.options.indent.is_some() && !spans.is_empty() {
+            let args: Vec<Vec<u8>> = spans
+                .iter()
+                .map(|&(from, to)| self.out[from..to].to_vec())
+                .collect();
+            self.out.truncate(start);
+            self.out.extend_from_slice(&args.join(&b", "[..]));
+        }
+        self.out.push(b')');
+        Ok(one_line && spans.is_empty())
+    }
+}
@@ -0,0 +1,528 @@
+// Compact binary serialization of a tree for `dumps_binary` /
+// `loads_binary`, meant for on-disk caches.
+//
+// Layout:
+//
+//     header   b"FWAB", format version (u8), Python major, minor (u8 each)
+//     strings  count, then each as length + UTF-8 (surrogatepass)
+//     value    the root: a node, or a list of nodes
+//
+// All counts, lengths, type codes and string indices are unsigned LEB128
+// varints. A value is a one-byte tag followed by its payload; a node is
+// its `type_vocabulary()` code, then one value per name in `_fields`
+// (`MISSING` for unset fields), then one position slot per name in
+// `_attributes`. Position slots are a single varint — 0 for unset, 1 for
+// `None`, 2 for "tagged value follows", else `zigzag(n) + 3` — so a
+// typical position costs one or two bytes. Every `str` goes through the
+// string table, so an identifier used a thousand times is stored once.
+//
+// Type codes are only stable for a given Python version, which is why
+// the header records it and loading rejects data from any other.
+
+use std::collections::HashMap;
+
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::ffi;
+use pyo3::prelude::*;
+use pyo3::types::{
+    PyBool, PyBytes, PyComplex, PyFloat, PyFrozenSet, PyInt, PyList, PyString, PyTuple, PyType,
+};
+
+use crate::fields::{FieldNames, NodeFields};
+use crate::{RecursionGuard, Walker, str_from_utf8, utf8, with_field_table};
+
+const MAGIC: &[u8; 4] = b"FWAB";
+/// Bumped whenever the layout changes; older data is then rejected.
+const FORMAT_VERSION: u8 = 1;
+
+// Value tags.
+const NONE: u8 = 0;
+const FALSE: u8 = 1;
+const TRUE: u8 = 2;
+/// Zigzag varint.
+const INT: u8 = 3;
+/// Length-prefixed hex digits, for ints outside `i64`.
+const BIG_INT: u8 = 4;
+/// Little-endian `f64`.
+const FLOAT: u8 = 5;
+const COMPLEX: u8 = 6;
+/// String table index.
+const STR: u8 = 7;
+const BYTES: u8 = 8;
+const ELLIPSIS: u8 = 9;
+const TUPLE: u8 = 10;
+const FROZENSET: u8 = 11;
+const LIST: u8 = 12;
+const NODE: u8 = 13;
+/// An unset field.
+const MISSING: u8 = 14;
+
+// Position slots.
+const POSITION_MISSING: u64 = 0;
+const POSITION_NONE: u64 = 1;
+const POSITION_VALUE: u64 = 2;
+const POSITION_INT: u64 = 3;
+
+fn python_version(py: Python<'_>) -> [u8; 2] {
+    let version = py.version_info();
+    [version.major, version.minor]
+}
+
+fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+fn unzigzag(n: u64) -> i64 {
+    (n >> 1) as i64 ^ -((n & 1) as i64)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+pub(crate) struct Encoder<'w, 'py> {
+    py: Python<'py>,
+    walker: &'w Walker<'w, 'py>,
+    fields: NodeFields<'py>,
+    strings: HashMap<Vec<u8>, u64>,
+    table: Vec<u8>,
+    body: Vec<u8>,
+}
+
+impl<'w, 'py> Encoder<'w, 'py> {
+    pub(crate) fn new(walker: &'w Walker<'w, 'py>) -> Self {
+        Self {
+            py: walker.py,
+            walker,
+            fields: NodeFields::new(walker.py),
+            strings: HashMap::new(),
+            table: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Header, string table and body, in that order.
+    pub(crate) fn finish(self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.table.len() + self.body.len() + 16);
+        out.extend_from_slice(MAGIC);
+        out.push(FORMAT_VERSION);
+        out.extend_from_slice(&python_version(self.py));
+        write_varint(&mut out, self.strings.len() as u64);
+        out.extend_from_slice(&self.table);
+        out.extend_from_slice(&self.body);
+        out
+    }
+
+    #[inline(never)]
+    fn string(&mut self, string: &Bound<'py, PyString>) -> PyResult<()> {
+        let bytes = utf8(string)?;
+        let index = match self.strings.get(&*bytes) {
+            Some(&index) => index,
+            None => {
+                let index = self.strings.len() as u64;
+                write_varint(&mut self.table, bytes.len() as u64);
+                self.table.extend_from_slice(&bytes);
+                self.strings.insert(bytes.into_owned(), index);
+                index
+            }
+        };
+        self.body.push(STR);
+        write_varint(&mut self.body, index);
+        Ok(())
+    }
+
+    fn items(
+        &mut self,
+        tag: u8,
+        len: usize,
+        items: impl Iterator<Item = Bound<'py, PyAny>>,
+    ) -> PyResult<()> {
+        let _guard = RecursionGuard::enter(self.py)?;
+        self.body.push(tag);
+        write_varint(&mut self.body, len as u64);
+        for item in items {
+            self.value(&item)?;
+        }
+        Ok(())
+    }
+
+    #[cold]
+    #[inline(never)]
+    fn big_int(&mut self, int: &Bound<'py, PyInt>) -> PyResult<()> {
+        // SAFETY: `int` is a live `int`; the result is an owned `str`.
+        let hex = unsafe {
+            Bound::from_owned_ptr_or_err(self.py, ffi::PyNumber_ToBase(int.as_ptr(), 16))?
+                .cast_into_unchecked::<PyString>()
+        };
+        let hex = hex.to_str()?;
+        self.body.push(BIG_INT);
+        write_varint(&mut self.body, hex.len() as u64);
+        self.body.extend_from_slice(hex.as_bytes());
+        Ok(())
+    }
+
+    pub(crate) fn value(&mut self, value: &Bound<'py, PyAny>) -> PyResult<()> {
+        if value.is_none() {
+            self.body.push(NONE);
+        } else if let Ok(flag) = value.cast::<PyBool>() {
+            self.body.push(if flag.is_true() { TRUE } else { FALSE });
+        } else if let Ok(string) = value.cast::<PyString>() {
+            self.string(string)?;
+        } else if let Ok(int) = value.cast::<PyInt>() {
+            match int.extract::<i64>() {
+                Ok(small) => {
+                    self.body.push(INT);
+                    write_varint(&mut self.body, zigzag(small));
+                }
+                Err(_) => self.big_int(int)?,
+            }
+        } else if let Ok(float) = value.cast::<PyFloat>() {
+            self.body.push(FLOAT);
+            self.body.extend_from_slice(&float.value().to_le_bytes());
+        } else if let Ok(list) = value.cast::<PyList>() {
+            self.items(LIST, list.len(), list.iter())?;
+        } else if self.walker.is_root(value) {
+            self.node(value)?;
+        } else if let Ok(bytes) = value.cast::<PyBytes>() {
+            let bytes = bytes.as_bytes();
+            self.body.push(BYTES);
+            write_varint(&mut self.body, bytes.len() as u64);
+            self.body.extend_from_slice(bytes);
+        } else if let Ok(complex) = value.cast::<PyComplex>() {
+            self.body.push(COMPLEX);
+            self.body.extend_from_slice(&complex.real().to_le_bytes());
+            self.body.extend_from_slice(&complex.imag().to_le_bytes());
+        } else if value.is(self.py.Ellipsis()) {
+            self.body.push(ELLIPSIS);
+        } else if let Ok(tuple) = value.cast::<PyTuple>() {
+            self.items(TUPLE, tuple.len(), tuple.iter())?;
+        } else if let Ok(set) = value.cast::<PyFrozenSet>() {
+            self.items(FROZENSET, set.len(), set.iter())?;
+        } else {
+            return Err(PyTypeError::new_err(format!(
+                "dumps_binary() can't serialize objects of type {}",
+                value.get_type().name()?
+            )));
+        }
+        Ok(())
+    }
+
+    /// The node's `type_vocabulary()` code. Only the stdlib classes
+    /// themselves have one: a subclass would come back as its base.
+    fn type_code(&self, node: &Bound<'py, PyAny>) -> PyResult<u64> {
+        let field_table = &*self.walker.field_table;
+        let node_type = node.get_type_ptr();
+        if let Some(slot) = field_table.slot_for(node_type) {
+            let code = field_table.codes[slot];
+            if field_table.vocabulary[usize::from(code)].as_ptr() == node_type.cast() {
+                return Ok(u64::from(code));
+            }
+        }
+        Err(PyTypeError::new_err(format!(
+            "dumps_binary() only supports the stdlib ast classes, got {}",
+            node.get_type().fully_qualified_name()?
+        )))
+    }
+
+    fn node(&mut self, node: &Bound<'py, PyAny>) -> PyResult<()> {
+        let _guard = RecursionGuard::enter(self.py)?;
+        let code = self.type_code(node)?;
+        self.body.push(NODE);
+        write_varint(&mut self.body, code);
+        for name in self.fields.names(node)?.iter() {
+            match self.fields.get(node, name)? {
+                Some(value) => self.value(&value)?,
+                None => self.body.push(MISSING),
+            }
+        }
+        for name in self.fields.attributes(node)?.iter() {
+            match self.fields.get(node, name)? {
+                None => write_varint(&mut self.body, POSITION_MISSING),
+                Some(value) if value.is_none() => write_varint(&mut self.body, POSITION_NONE),
+                Some(value) => match value
+                    .is_exact_instance_of::<PyInt>()
+                    .then(|| value.extract::<i64>().ok())
+                    .flatten()
+                    .and_then(|n| zigzag(n).checked_add(POSITION_INT))
+                {
+                    Some(slot) => write_varint(&mut self.body, slot),
+                    None => {
+                        write_varint(&mut self.body, POSITION_VALUE);
+                        self.value(&value)?;
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A vocabulary class, with what `loads_binary` needs to rebuild its
+/// instances.
+struct NodeClass<'py> {
+    class: Bound<'py, PyType>,
+    fields: FieldNames<'py>,
+    attributes: FieldNames<'py>,
+    /// The one instance of a class with neither fields nor attributes
+    /// (`Load`, `Add`, ...), shared the way `ast.parse` shares them.
+    singleton: Option<Bound<'py, PyAny>>,
+}
+
+pub(crate) struct Decoder<'d, 'py> {
+    py: Python<'py>,
+    data: &'d [u8],
+    pos: usize,
+    strings: Vec<Bound<'py, PyString>>,
+    vocabulary: Vec<Bound<'py, PyType>>,
+    classes: Vec<Option<NodeClass<'py>>>,
+}
+
+impl<'d, 'py> Decoder<'d, 'py> {
+    pub(crate) fn new(py: Python<'py>, data: &'d [u8]) -> PyResult<Self> {
+        let vocabulary: Vec<_> = with_field_table(py, |field_table| {
+            Ok(field_table
+                .vocabulary
+                .iter()
+                .map(|class| class.bind(py).clone())
+                .collect())
+        })?;
+        let mut decoder = Self {
+            py,
+            data,
+            pos: 0,
+            strings: Vec::new(),
+            classes: vocabulary.iter().map(|_| None).collect(),
+            vocabulary,
+        };
+        decoder.header()?;
+        Ok(decoder)
+    }
+
+    /// Parse the body, which must be exactly one value.
+    pub(crate) fn read(mut self) -> PyResult<Bound<'py, PyAny>> {
+        let value = self.value()?;
+        if self.pos != self.data.len() {
+            return Err(self.error("unexpected data after the tree"));
+        }
+        Ok(value)
+    }
+
+    fn error(&self, message: &str) -> PyErr {
+        PyValueError::new_err(format!("loads_binary(): {message} (at byte {})", self.pos))
+    }
+
+    fn header(&mut self) -> PyResult<()> {
+        if !self.data.starts_with(MAGIC) {
+            return Err(PyValueError::new_err(
+                "loads_binary(): not dumps_binary() output (bad magic number)",
+            ));
+        }
+        self.pos = MAGIC.len();
+        let [version, major, minor] = *self.bytes(3)? else {
+            unreachable!()
+        };
+        if version != FORMAT_VERSION {
+            return Err(PyValueError::new_err(format!(
+                "loads_binary(): data uses format version {version}, but this fast_walk reads \
+                 version {FORMAT_VERSION}; re-serialize it"
+            )));
+        }
+        let [current_major, current_minor] = python_version(self.py);
+        if [major, minor] != [current_major, current_minor] {
+            return Err(PyValueError::new_err(format!(
+                "loads_binary(): data was written by Python {major}.{minor}, but this is \
+                 Python {current_major}.{current_minor}; re-serialize it"
+            )));
+        }
+        let count = self.length()?;
+        self.strings.reserve(count);
+        for _ in 0..count {
+            let len = self.length()?;
+            let bytes = self.bytes(len)?;
+            let string = str_from_utf8(self.py, bytes)?;
+            self.strings.push(string);
+        }
+        Ok(())
+    }
+
+    fn bytes(&mut self, len: usize) -> PyResult<&'d [u8]> {
+        let data = self.data;
+        match self
+            .pos
+            .checked_add(len)
+            .and_then(|end| data.get(self.pos..end))
+        {
+            Some(bytes) => {
+                self.pos += len;
+                Ok(bytes)
+            }
+            None => Err(self.error("truncated data")),
+        }
+    }
+
+    fn byte(&mut self) -> PyResult<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn varint(&mut self) -> PyResult<u64> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            n |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(self.error("malformed varint"))
+    }
+
+    /// A varint counting bytes or items still to come, which can't
+    /// exceed what is left of the data.
+    fn length(&mut self) -> PyResult<usize> {
+        match usize::try_from(self.varint()?) {
+            Ok(len) if len <= self.data.len() - self.pos => Ok(len),
+            _ => Err(self.error("truncated data")),
+        }
+    }
+
+    fn f64(&mut self) -> PyResult<f64> {
+        Ok(f64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn items(&mut self) -> PyResult<Vec<Bound<'py, PyAny>>> {
+        let _guard = RecursionGuard::enter(self.py)?;
+        let len = self.length()?;
+        let mut items = Vec::with_capacity(len);
+        for _ in 0..len {
+            items.push(self.value()?);
+        }
+        Ok(items)
+    }
+
+    fn value(&mut self) -> PyResult<Bound<'py, PyAny>> {
+        let tag = self.byte()?;
+        self.tagged(tag)?
+            .ok_or_else(|| self.error(&format!("unexpected tag {tag}")))
+    }
+
+    /// The value `tag` introduces, or `None` if `tag` is `MISSING` (only
+    /// valid for a node field) or unknown.
+    fn tagged(&mut self, tag: u8) -> PyResult<Option<Bound<'py, PyAny>>> {
+        let py = self.py;
+        Ok(Some(match tag {
+            NONE => py.None().into_bound(py),
+            FALSE => PyBool::new(py, false).to_owned().into_any(),
+            TRUE => PyBool::new(py, true).to_owned().into_any(),
+            INT => unzigzag(self.varint()?).into_pyobject(py)?.into_any(),
+            BIG_INT => {
+                let len = self.length()?;
+                let hex = std::str::from_utf8(self.bytes(len)?)
+                    .map_err(|_| self.error("malformed integer"))?;
+                py.get_type::<PyInt>().call1((hex, 16))?
+            }
+            FLOAT => PyFloat::new(py, self.f64()?).into_any(),
+            COMPLEX => {
+                let real = self.f64()?;
+                PyComplex::from_doubles(py, real, self.f64()?).into_any()
+            }
+            STR => {
+                let index = self.varint()?;
+                match usize::try_from(index)
+                    .ok()
+                    .and_then(|i| self.strings.get(i))
+                {
+                    Some(string) => string.clone().into_any(),
+                    None => return Err(self.error("string index out of range")),
+                }
+            }
+            BYTES => {
+                let len = self.length()?;
+                PyBytes::new(py, self.bytes(len)?).into_any()
+            }
+            ELLIPSIS => py.Ellipsis().into_bound(py),
+            TUPLE => PyTuple::new(py, self.items()?)?.into_any(),
+            FROZENSET => PyFrozenSet::new(py, self.items()?)?.into_any(),
+            LIST => PyList::new(py, self.items()?)?.into_any(),
+            NODE => self.node()?,
+            _ => return Ok(None),
+        }))
+    }
+
+    fn node_class(&mut self, code: u64) -> PyResult<&mut NodeClass<'py>> {
+        let Some(code) = usize::try_from(code)
+            .ok()
+            .filter(|&code| code < self.vocabulary.len())
+        else {
+            return Err(self.error("type code out of range"));
+        };
+        if self.classes[code].is_none() {
+            let class = self.vocabulary[code].clone();
+            let names = |attribute: &str| -> PyResult<FieldNames<'py>> {
+                match class.getattr(attribute) {
+                    Ok(names) => names
+                        .try_iter()?
+                        .map(|name| Ok(name?.cast_into::<PyString>()?))
+                        .collect(),
+                    Err(_) => Ok(FieldNames::from([])),
+                }
+            };
+            let (fields, attributes) = (names("_fields")?, names("_attributes")?);
+            self.classes[code] = Some(NodeClass {
+                class,
+                fields,
+                attributes,
+                singleton: None,
+            });
+        }
+        Ok(self.classes[code].as_mut().unwrap())
+    }
+
+    fn node(&mut self) -> PyResult<Bound<'py, PyAny>> {
+        let _guard = RecursionGuard::enter(self.py)?;
+        let py = self.py;
+        let code = self.varint()?;
+        let node_class = self.node_class(code)?;
+        if let Some(singleton) = &node_class.singleton {
+            return Ok(singleton.clone());
+        }
+        let class = node_class.class.clone();
+        let (fields, attributes) = (node_class.fields.clone(), node_class.attributes.clone());
+        // `class.__new__(class)`: an instance with no fields set, which
+        // the constructor would fill with defaults (and warn about).
+        let node = unsafe {
+            let type_ptr = class.as_type_ptr();
+            let Some(tp_new) = (*type_ptr).tp_new else {
+                return Err(PyTypeError::new_err("cannot create node instances"));
+            };
+            Bound::from_owned_ptr_or_err(
+                py,
+                tp_new(type_ptr, PyTuple::empty(py).as_ptr(), std::ptr::null_mut()),
+            )?
+        };
+        for name in fields.iter() {
+            let tag = self.byte()?;
+            if tag != MISSING {
+                let value = self
+                    .tagged(tag)?
+                    .ok_or_else(|| self.error(&format!("unexpected tag {tag}")))?;
+                node.setattr(name, value)?;
+            }
+        }
+        for name in attributes.iter() {
+            match self.varint()? {
+                POSITION_MISSING => {}
+                POSITION_NONE => node.setattr(name, py.None())?,
+                POSITION_VALUE => node.setattr(name, self.value()?)?,
+                slot => node.setattr(name, unzigzag(slot - POSITION_INT))?,
+            }
+        }
+        if fields.is_empty() && attributes.is_empty() {
+            self.node_class(code)?.singleton = Some(node.clone());
+        }
+        Ok(node)
+    }
+}
@@ -0,0 +1,120 @@
+// Structural comparison for `ast_equal`: Python 3.14's `ast.compare`.
+//
+// Both trees are walked in lockstep over `_fields` (and `_attributes`,
+// if asked), stopping at the first difference. The location of that
+// difference is only assembled on the way back out of the recursion, so
+// comparing equal trees costs nothing extra.
+
+use std::fmt::Write as _;
+
+use pyo3::prelude::*;
+use pyo3::types::{PyList, PyString};
+
+use crate::fields::NodeFields;
+use crate::{RecursionGuard, Walker};
+
+enum Step<'py> {
+    Field(Bound<'py, PyString>),
+    Index(usize),
+}
+
+pub(crate) struct Comparer<'w, 'py> {
+    py: Python<'py>,
+    walker: &'w Walker<'w, 'py>,
+    fields: NodeFields<'py>,
+    compare_attributes: bool,
+    /// Steps from the first difference back up to the roots.
+    path: Vec<Step<'py>>,
+}
+
+impl<'w, 'py> Comparer<'w, 'py> {
+    pub(crate) fn new(walker: &'w Walker<'w, 'py>, compare_attributes: bool) -> Self {
+        Self {
+            py: walker.py,
+            walker,
+            fields: NodeFields::new(walker.py),
+            compare_attributes,
+            path: Vec::new(),
+        }
+    }
+
+    /// `None` if `a` and `b` are equal, otherwise the path to their first
+    /// difference, e.g. `$.body[0].value.id`.
+    pub(crate) fn compare(
+        mut self,
+        a: &Bound<'py, PyAny>,
+        b: &Bound<'py, PyAny>,
+    ) -> PyResult<Option<String>> {
+        if self.value(a, b)? {
+            return Ok(None);
+        }
+        let mut path = String::from("$");
+        for step in self.path.iter().rev() {
+            match step {
+                Step::Field(name) => {
+                    path.push('.');
+                    path.push_str(&name.to_string_lossy());
+                }
+                Step::Index(i) => write!(path, "[{i}]").unwrap(),
+            }
+        }
+        Ok(Some(path))
+    }
+
+    /// `ast.compare`'s `_compare`: nodes structurally, lists item by
+    /// item, anything else by `type(a) is type(b) and a == b`.
+    fn value(&mut self, a: &Bound<'py, PyAny>, b: &Bound<'py, PyAny>) -> PyResult<bool> {
+        if self.walker.is_root(a) {
+            return self.node(a, b);
+        }
+        let Ok(a) = a.cast::<PyList>() else {
+            return Ok(a.get_type().is(b.get_type()) && a.eq(b)?);
+        };
+        let Ok(b) = b.cast::<PyList>() else {
+            return Ok(false);
+        };
+        if a.len() != b.len() {
+            return Ok(false);
+        }
+        let _guard = RecursionGuard::enter(self.py)?;
+        for (i, (a, b)) in a.iter().zip(b.iter()).enumerate() {
+            if !self.value(&a, &b)? {
+                self.path.push(Step::Index(i));
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn node(&mut self, a: &Bound<'py, PyAny>, b: &Bound<'py, PyAny>) -> PyResult<bool> {
+        if !a.get_type().is(b.get_type()) {
+            return Ok(false);
+        }
+        let _guard = RecursionGuard::enter(self.py)?;
+        // A field unset on both nodes is no difference.
+        for name in self.fields.names(a)?.iter() {
+            let equal = match (self.fields.get(a, name)?, self.fields.get(b, name)?) {
+                (Some(a), Some(b)) => self.value(&a, &b)?,
+                (a, b) => a.is_none() && b.is_none(),
+            };
+            if !equal {
+                self.path.push(Step::Field(name.clone()));
+                return Ok(false);
+            }
+        }
+        if self.compare_attributes {
+            // Positions are plain values: `a != b`, without the type check.
+            for name in self.fields.attributes(a)?.iter() {
+                let equal = match (self.fields.get(a, name)?, self.fields.get(b, name)?) {
+                    (Some(a), Some(b)) => !a.ne(b)?,
+                    (a, b) => a.is_none() && b.is_none(),
+                };
+                if !equal {
+                    self.path.push(Step::Field(name.clone()));
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    }
+}
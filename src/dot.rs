@@ -0,0 +1,192 @@
+// Graphviz DOT rendering of a tree for `to_dot`.
+//
+// One depth-first pass over the field-aware traversal writes a vertex
+// per node, labelled with its type and its scalar fields (`id='x'`,
+// `value=1`, ...), and an edge from its parent labelled with the field
+// that holds it (`body[0]`, `func`). Vertices are named `n0`, `n1`, ...
+// in depth-first pre-order over `_fields`, the order of
+// `ast.iter_child_nodes`. That is `walk_dfs` order unless a node's
+// constructor stored its fields in another order.
+//
+// The pass keeps its path from the root, so a tree that contains itself
+// raises `ValueError`, as `walk_dfs(detect_cycles=True)` does, instead of
+// rendering forever.
+
+use std::fmt::Write as _;
+
+use pyo3::ffi::PyObject;
+use pyo3::intern;
+use pyo3::prelude::*;
+use pyo3::types::{PyList, PyString};
+
+use crate::fields::NodeFields;
+use crate::{PtrSet, Walker, cycle_error};
+
+/// Scalar values longer than this (in characters, as `repr()`ed) are
+/// cut short in labels; docstrings would otherwise swamp the graph.
+const MAX_VALUE_CHARS: usize = 40;
+
+struct Pending<'py> {
+    node: Bound<'py, PyAny>,
+    /// Number of ancestors.
+    depth: usize,
+    parent: Option<usize>,
+    field: Option<Bound<'py, PyString>>,
+    index: Option<usize>,
+}
+
+/// Append `text` as the inside of a DOT double-quoted string.
+fn push_escaped(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            _ => out.push(c),
+        }
+    }
+}
+
+/// `repr(value)`, shortened to [`MAX_VALUE_CHARS`].
+fn short_repr(value: &Bound<'_, PyAny>) -> PyResult<String> {
+    let repr = value.repr()?;
+    let repr = repr.to_string_lossy();
+    if repr.chars().count() <= MAX_VALUE_CHARS {
+        return Ok(repr.into_owned());
+    }
+    let mut short: String = repr.chars().take(MAX_VALUE_CHARS - 3).collect();
+    short.push_str("...");
+    Ok(short)
+}
+
+/// `lineno:col_offset-end_lineno:end_col_offset`, or as much of it as
+/// the node has.
+fn position<'py>(fields: &NodeFields<'py>, node: &Bound<'py, PyAny>) -> PyResult<Option<String>> {
+    let py = node.py();
+    let int = |name| -> PyResult<Option<i64>> {
+        Ok(fields
+            .get(node, name)?
+            .and_then(|value| value.extract().ok()))
+    };
+    let (Some(line), Some(col)) = (int(intern!(py, "lineno"))?, int(intern!(py, "col_offset"))?)
+    else {
+        return Ok(None);
+    };
+    let mut position = format!("{line}:{col}");
+    if let (Some(end_line), Some(end_col)) = (
+        int(intern!(py, "end_lineno"))?,
+        int(intern!(py, "end_col_offset"))?,
+    ) {
+        write!(position, "-{end_line}:{end_col}").unwrap();
+    }
+    Ok(Some(position))
+}
+
+/// Render `roots` as a `digraph`.
+pub(crate) fn to_dot(
+    walker: &mut Walker<'_, '_>,
+    roots: &[*mut PyObject],
+    show_positions: bool,
+) -> PyResult<String> {
+    let py = walker.py;
+    let mut fields = NodeFields::new(py);
+    let mut out = String::from("digraph AST {\n  node [shape=box];\n");
+    let mut stack: Vec<Pending<'_>> = roots
+        .iter()
+        .rev()
+        // SAFETY: the roots are alive for the whole call.
+        .map(|&root| Pending {
+            node: unsafe { Bound::from_borrowed_ptr(py, root) },
+            depth: 0,
+            parent: None,
+            field: None,
+            index: None,
+        })
+        .collect();
+    let mut children = Vec::new();
+    let mut count = 0;
+    let mut path: Vec<Bound<'_, PyAny>> = Vec::new();
+    let mut on_path = PtrSet::default();
+
+    while let Some(Pending {
+        node,
+        depth,
+        parent,
+        field,
+        index,
+    }) = stack.pop()
+    {
+        for finished in path.drain(depth..) {
+            on_path.remove(&finished.as_ptr());
+        }
+        if !on_path.insert(node.as_ptr()) {
+            return Err(cycle_error(py, path[depth - 1].as_ptr(), node.as_ptr()));
+        }
+        path.push(node.clone());
+
+        let id = count;
+        count += 1;
+        walker.check_budget(count)?;
+
+        let class = node.get_type();
+        let mut label = class.name()?.to_string();
+        for name in fields.names(&node)?.iter() {
+            let Some(value) = fields.get(&node, name)? else {
+                continue;
+            };
+            if let Ok(list) = value.cast::<PyList>() {
+                let mut has_nodes = false;
+                for (i, item) in list.iter().enumerate() {
+                    if walker.is_ast(&item) {
+                        has_nodes = true;
+                        children.push(Pending {
+                            node: item,
+                            depth: depth + 1,
+                            parent: Some(id),
+                            field: Some(name.clone()),
+                            index: Some(i),
+                        });
+                    }
+                }
+                // Lists of plain values, like `Global.names`.
+                if !has_nodes && !list.is_empty() {
+                    write!(label, "\n{name}={}", short_repr(&value)?).unwrap();
+                }
+            } else if walker.is_ast(&value) {
+                children.push(Pending {
+                    node: value,
+                    depth: depth + 1,
+                    parent: Some(id),
+                    field: Some(name.clone()),
+                    index: None,
+                });
+            } else if !(value.is_none()
+                && class.getattr(name).is_ok_and(|default| default.is_none()))
+            {
+                // Like `ast.dump`, leave out optional fields that are unset.
+                write!(label, "\n{name}={}", short_repr(&value)?).unwrap();
+            }
+        }
+        if show_positions && let Some(position) = position(&fields, &node)? {
+            write!(label, "\n{position}").unwrap();
+        }
+
+        write!(out, "  n{id} [label=\"").unwrap();
+        push_escaped(&mut out, &label);
+        out.push_str("\"];\n");
+        if let Some(parent) = parent {
+            write!(out, "  n{parent} -> n{id} [label=\"").unwrap();
+            if let Some(field) = &field {
+                push_escaped(&mut out, &field.to_string_lossy());
+            }
+            if let Some(index) = index {
+                write!(out, "[{index}]").unwrap();
+            }
+            out.push_str("\"];\n");
+        }
+        stack.extend(children.drain(..).rev());
+    }
+
+    out.push_str("}\n");
+    Ok(out)
+}
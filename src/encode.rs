@@ -0,0 +1,239 @@
+// Flattened, column-per-attribute encoding of a tree for `encode_tree`.
+//
+// One depth-first pass records, for every node, its type code, its
+// parent's index, its depth and its `lineno`/`col_offset` into plain
+// `i32` vectors. Each vector is handed to Python as an `Int32Array`,
+// which exposes it through the buffer protocol: `memoryview`,
+// `numpy.frombuffer`, `array`-style consumers and `bytes()` read it in
+// place, with no NumPy dependency on our side.
+
+use std::ffi::{c_int, c_void};
+
+use pyo3::exceptions::{PyBufferError, PyOverflowError};
+use pyo3::ffi::{self, PyObject};
+use pyo3::intern;
+use pyo3::prelude::*;
+use pyo3::types::PyTuple;
+
+use crate::{Walker, get_instance_dict_fast, process_node, with_field_table};
+
+/// Value stored for a missing parent (roots), a missing or non-integer
+/// position, and the type code of non-AST roots.
+const MISSING: i32 = -1;
+
+/// Read-only, one-dimensional array of C `int` (`int32`) values that
+/// supports the buffer protocol, e.g. `numpy.frombuffer(a,
+/// dtype=numpy.int32)` or `memoryview(a).tolist()`.
+#[pyclass(module = "fast_walk", frozen)]
+pub struct Int32Array {
+    data: Box<[i32]>,
+    // Backing storage for `Py_buffer::shape` / `strides`, which must
+    // stay valid for as long as the exporter is alive.
+    shape: [ffi::Py_ssize_t; 1],
+    strides: [ffi::Py_ssize_t; 1],
+}
+
+impl Int32Array {
+    fn new(data: Vec<i32>) -> Self {
+        let data = data.into_boxed_slice();
+        let len = data.len() as ffi::Py_ssize_t;
+        Self {
+            data,
+            shape: [len],
+            strides: [size_of::<i32>() as ffi::Py_ssize_t],
+        }
+    }
+}
+
+#[pymethods]
+impl Int32Array {
+    fn __len__(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Export the data as a contiguous, read-only buffer. The array is
+    /// frozen and its storage never moves, so views stay valid for as
+    /// long as they hold their reference in `obj`.
+    unsafe fn __getbuffer__(
+        slf: Bound<'_, Self>,
+        view: *mut ffi::Py_buffer,
+        flags: c_int,
+    ) -> PyResult<()> {
+        if flags & ffi::PyBUF_WRITABLE == ffi::PyBUF_WRITABLE {
+            return Err(PyBufferError::new_err("Int32Array is read-only"));
+        }
+        let this = slf.get();
+        let requested = |flag: c_int| flags & flag == flag;
+        unsafe {
+            (*view).buf = this.data.as_ptr().cast_mut().cast::<c_void>();
+            (*view).len = this.shape[0] * this.strides[0];
+            (*view).readonly = 1;
+            (*view).itemsize = this.strides[0];
+            (*view).format = if requested(ffi::PyBUF_FORMAT) {
+                c"i".as_ptr().cast_mut()
+            } else {
+                std::ptr::null_mut()
+            };
+            (*view).ndim = 1;
+            (*view).shape = if requested(ffi::PyBUF_ND) {
+                this.shape.as_ptr().cast_mut()
+            } else {
+                std::ptr::null_mut()
+            };
+            (*view).strides = if requested(ffi::PyBUF_STRIDES) {
+                this.strides.as_ptr().cast_mut()
+            } else {
+                std::ptr::null_mut()
+            };
+            (*view).suboffsets = std::ptr::null_mut();
+            (*view).internal = std::ptr::null_mut();
+            (*view).obj = slf.into_ptr();
+        }
+        Ok(())
+    }
+}
+
+/// Result of [`encode_tree`](crate::encode_tree): one [`Int32Array`]
+/// per attribute, all indexed by the node's position in depth-first
+/// pre-order.
+#[pyclass(module = "fast_walk", frozen)]
+pub struct EncodedTree {
+    /// Index into `type_vocabulary()`.
+    #[pyo3(get)]
+    type_code: Py<Int32Array>,
+    /// Index of the parent node, `-1` for roots.
+    #[pyo3(get)]
+    parent: Py<Int32Array>,
+    /// Distance from the node's root, which has depth 0.
+    #[pyo3(get)]
+    depth: Py<Int32Array>,
+    #[pyo3(get)]
+    lineno: Py<Int32Array>,
+    #[pyo3(get)]
+    col_offset: Py<Int32Array>,
+}
+
+#[pymethods]
+impl EncodedTree {
+    fn __len__(&self) -> usize {
+        self.type_code.get().data.len()
+    }
+}
+
+/// Column buffers filled by [`encode_roots`].
+#[derive(Default)]
+struct Columns {
+    type_code: Vec<i32>,
+    parent: Vec<i32>,
+    depth: Vec<i32>,
+    lineno: Vec<i32>,
+    col_offset: Vec<i32>,
+}
+
+impl Columns {
+    fn into_pyobject(self, py: Python<'_>) -> PyResult<EncodedTree> {
+        let column = |data| Py::new(py, Int32Array::new(data));
+        Ok(EncodedTree {
+            type_code: column(self.type_code)?,
+            parent: column(self.parent)?,
+            depth: column(self.depth)?,
+            lineno: column(self.lineno)?,
+            col_offset: column(self.col_offset)?,
+        })
+    }
+}
+
+/// `int(node.__dict__[name])` if that is an `int` that fits in 32 bits,
+/// else [`MISSING`]. Only called on AST nodes, whose instance dict sits
+/// at a fixed positive `tp_dictoffset`.
+unsafe fn int_attribute(py: Python<'_>, node: *mut PyObject, name: *mut PyObject) -> PyResult<i32> {
+    let Some(dict) = get_instance_dict_fast(node) else {
+        return Ok(MISSING);
+    };
+    unsafe {
+        let value = ffi::PyDict_GetItemWithError(dict, name);
+        if value.is_null() {
+            return match PyErr::take(py) {
+                Some(err) => Err(err),
+                None => Ok(MISSING),
+            };
+        }
+        if ffi::PyLong_Check(value) == 0 {
+            return Ok(MISSING);
+        }
+        let mut overflow = 0;
+        let value = ffi::PyLong_AsLongAndOverflow(value, &mut overflow);
+        if overflow != 0 {
+            return Ok(MISSING);
+        }
+        Ok(i32::try_from(value).unwrap_or(MISSING))
+    }
+}
+
+/// Depth-first pre-order walk over `roots` that appends one row per
+/// node to `columns`. Same traversal as `walk_node_dfs`, with each
+/// stack entry carrying its parent's row and its depth.
+fn encode_roots(
+    walker: &mut Walker<'_, '_>,
+    roots: &[*mut PyObject],
+    columns: &mut Columns,
+) -> PyResult<()> {
+    let py = walker.py;
+    let lineno = intern!(py, "lineno").as_ptr();
+    let col_offset = intern!(py, "col_offset").as_ptr();
+    let mut stack: Vec<_> = roots.iter().rev().map(|&root| (root, MISSING, 0)).collect();
+    let mut children = Vec::new();
+
+    while let Some((node, parent, depth)) = stack.pop() {
+        let row = columns.type_code.len();
+        walker.check_budget(row + 1)?;
+        let index = i32::try_from(row)
+            .map_err(|_| PyOverflowError::new_err("encode_tree() supports at most 2**31 nodes"))?;
+        // Classifies the node's type (late types included) before its
+        // code is read back below.
+        unsafe {
+            process_node(walker, node, &mut children, |w| {
+                w.pin(stack.iter().map(|&(node, ..)| node));
+            })?
+        };
+        let code = walker
+            .field_table
+            .slot_for(unsafe { ffi::Py_TYPE(node) })
+            .map_or(MISSING, |slot| i32::from(walker.field_table.codes[slot]));
+        let (line, col) = if code == MISSING {
+            (MISSING, MISSING)
+        } else {
+            unsafe {
+                (
+                    int_attribute(py, node, lineno)?,
+                    int_attribute(py, node, col_offset)?,
+                )
+            }
+        };
+        columns.type_code.push(code);
+        columns.parent.push(parent);
+        columns.depth.push(depth);
+        columns.lineno.push(line);
+        columns.col_offset.push(col);
+        stack.extend(children.drain(..).map(|child| (child, index, depth + 1)));
+    }
+
+    Ok(())
+}
+
+/// Walk `roots` and build the [`EncodedTree`] for them.
+pub(crate) fn encode(
+    walker: &mut Walker<'_, '_>,
+    roots: &[*mut PyObject],
+) -> PyResult<EncodedTree> {
+    let mut columns = Columns::default();
+    encode_roots(walker, roots, &mut columns)?;
+    columns.into_pyobject(walker.py)
+}
+
+/// The stdlib node classes, indexed by their type code.
+pub(crate) fn vocabulary(py: Python<'_>) -> PyResult<Bound<'_, PyTuple>> {
+    with_field_table(py, |field_table| {
+        PyTuple::new(py, field_table.vocabulary.iter().map(|t| t.bind(py)))
+    })
+}
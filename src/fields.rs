@@ -0,0 +1,132 @@
+// Field-aware access to AST nodes for the exporters.
+//
+// The walk loops only need a node's children, so they read the first
+// `len(_fields)` values straight out of the instance dict in storage
+// order. Exporters also need each child's field name, and must see the
+// fields exactly as the `ast` module does: in `_fields` order, whatever
+// order the constructor stored them in. So they go by name — each name
+// in `_fields` looked up in the instance dict, with `getattr` as the
+// fallback for values that live elsewhere (class-level defaults,
+// properties on user subclasses).
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::hash::BuildHasherDefault;
+use std::rc::Rc;
+
+use pyo3::exceptions::PyAttributeError;
+use pyo3::ffi::{self, PyTypeObject};
+use pyo3::intern;
+use pyo3::prelude::*;
+use pyo3::types::PyString;
+
+use crate::{PtrHasher, get_instance_dict_fast};
+
+/// Names of a node's fields, in `_fields` order.
+pub(crate) type FieldNames<'py> = Rc<[Bound<'py, PyString>]>;
+
+/// A node type's `_fields` and `_attributes`. `fields` is `None` for a
+/// `_fast_walk_dynamic_fields` type, whose instances carry their own.
+#[derive(Clone)]
+struct TypeFields<'py> {
+    fields: Option<FieldNames<'py>>,
+    attributes: FieldNames<'py>,
+}
+
+/// Per-call cache of [`TypeFields`] by node type.
+pub(crate) struct NodeFields<'py> {
+    py: Python<'py>,
+    by_type: HashMap<*mut PyTypeObject, TypeFields<'py>, BuildHasherDefault<PtrHasher>>,
+}
+
+impl<'py> NodeFields<'py> {
+    pub(crate) fn new(py: Python<'py>) -> Self {
+        Self {
+            py,
+            by_type: HashMap::default(),
+        }
+    }
+
+    fn type_fields(&mut self, node: &Bound<'py, PyAny>) -> PyResult<&TypeFields<'py>> {
+        let py = self.py;
+        let entry = match self.by_type.entry(node.get_type_ptr()) {
+            Entry::Occupied(entry) => return Ok(entry.into_mut()),
+            Entry::Vacant(entry) => entry,
+        };
+        let node_type = node.get_type();
+        let dynamic = node_type
+            .getattr(intern!(py, "_fast_walk_dynamic_fields"))
+            .and_then(|flag| flag.is_truthy())
+            .unwrap_or(false);
+        let fields = if dynamic {
+            None
+        } else {
+            Some(field_names(&node_type.getattr(intern!(py, "_fields"))?)?)
+        };
+        let attributes = match node_type.getattr(intern!(py, "_attributes")) {
+            Ok(attributes) => field_names(&attributes)?,
+            Err(_) => Rc::new([]),
+        };
+        Ok(entry.insert(TypeFields { fields, attributes }))
+    }
+
+    /// `node._fields`.
+    pub(crate) fn names(&mut self, node: &Bound<'py, PyAny>) -> PyResult<FieldNames<'py>> {
+        match &self.type_fields(node)?.fields {
+            Some(names) => Ok(names.clone()),
+            None => field_names(&node.getattr(intern!(self.py, "_fields"))?),
+        }
+    }
+
+    /// `type(node)._attributes` (positions, for the stdlib classes), or
+    /// nothing if the type doesn't define it.
+    pub(crate) fn attributes(&mut self, node: &Bound<'py, PyAny>) -> PyResult<FieldNames<'py>> {
+        Ok(self.type_fields(node)?.attributes.clone())
+    }
+
+    /// `getattr(node, name)`, or `None` where that raises
+    /// `AttributeError` — the way `ast.dump` and `ast.iter_fields` skip
+    /// unset fields.
+    pub(crate) fn get(
+        &self,
+        node: &Bound<'py, PyAny>,
+        name: &Bound<'py, PyString>,
+    ) -> PyResult<Option<Bound<'py, PyAny>>> {
+        let py = self.py;
+        if let Some(dict) = get_instance_dict_fast(node.as_ptr()) {
+            // SAFETY: `dict` is the live instance dict of `node`; the
+            // value is borrowed from it and promoted to a strong ref
+            // before any other Python code runs.
+            let value = unsafe { ffi::PyDict_GetItemWithError(dict, name.as_ptr()) };
+            if !value.is_null() {
+                return Ok(Some(unsafe { Bound::from_borrowed_ptr(py, value) }));
+            }
+            if let Some(err) = PyErr::take(py) {
+                return Err(err);
+            }
+        }
+        match node.getattr(name) {
+            Ok(value) => Ok(Some(value)),
+            Err(err) if err.is_instance_of::<PyAttributeError>(py) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Collect a `_fields` sequence, checking that every name is a `str`.
+fn field_names<'py>(fields: &Bound<'py, PyAny>) -> PyResult<FieldNames<'py>> {
+    fields
+        .try_iter()?
+        .map(|name| Ok(name?.cast_into::<PyString>()?))
+        .collect()
+}
+
+/// Whether `name` is an ASCII identifier, which the exporters write bare
+/// (`$.body`, `(Name print)`) rather than quoted.
+pub(crate) fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
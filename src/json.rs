@@ -0,0 +1,789 @@
+// JSON serialization of a tree for `to_json`, and the inverse,
+// `from_json`.
+//
+// Nodes become objects tagged with their class name, `{"_type": "Call",
+// "func": ..., "args": [...]}`, fields in `_fields` order followed by
+// the positions in `_attributes`. Values JSON has no literal for are
+// tagged objects too: `{"_type": "bytes", "base64": ...}`,
+// `{"_type": "complex", "real": ..., "imag": ...}`, `{"_type":
+// "Ellipsis"}`, `{"_type": "float", "value": "inf"}` (and `-inf`,
+// `nan`), and `{"_type": "tuple" | "frozenset", "elts": [...]}`.
+//
+// Output is byte-for-byte what `json.dumps` (default `ensure_ascii`,
+// and `indent` as given) produces for the equivalent dicts, so strings
+// are ASCII-only and lone surrogates survive as `\udXXX` escapes.
+//
+// `from_json` reads that format back. Node types are looked up by name
+// among the stdlib classes the field table enumerates (the
+// `type_vocabulary()`), and every member must be one of the class's
+// `_fields` or `_attributes`; anything else is a `ValueError` naming the
+// offending JSON path.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::ffi;
+use pyo3::intern;
+use pyo3::prelude::*;
+use pyo3::types::{
+    PyBool, PyBytes, PyComplex, PyDict, PyFloat, PyFrozenSet, PyInt, PyList, PyString,
+    PyStringData, PyTuple, PyType,
+};
+
+use crate::fields::{NodeFields, is_identifier};
+use crate::{RecursionGuard, Walker, with_field_table};
+
+pub(crate) struct JsonWriter<'w, 'py> {
+    py: Python<'py>,
+    walker: &'w Walker<'w, 'py>,
+    fields: NodeFields<'py>,
+    include_positions: bool,
+    indent: Option<usize>,
+    level: usize,
+    out: String,
+}
+
+impl<'w, 'py> JsonWriter<'w, 'py> {
+    pub(crate) fn new(
+        walker: &'w Walker<'w, 'py>,
+        include_positions: bool,
+        indent: Option<usize>,
+    ) -> Self {
+        Self {
+            py: walker.py,
+            walker,
+            fields: NodeFields::new(walker.py),
+            include_positions,
+            indent,
+            level: 0,
+            out: String::new(),
+        }
+    }
+
+    pub(crate) fn finish(self) -> String {
+        self.out
+    }
+
+    /// Line break and indentation before an array item or object member
+    /// (`first` is false from the second one on).
+    fn separator(&mut self, first: bool) {
+        if !first {
+            self.out.push(',');
+        }
+        match self.indent {
+            Some(indent) => {
+                self.out.push('\n');
+                self.out
+                    .extend(std::iter::repeat_n(' ', indent * self.level));
+            }
+            None if !first => self.out.push(' '),
+            None => {}
+        }
+    }
+
+    /// Open `[` or `{`; the matching [`close`](Self::close) needs to
+    /// know whether anything was written in between.
+    fn open(&mut self, bracket: char) {
+        self.out.push(bracket);
+        self.level += 1;
+    }
+
+    fn close(&mut self, bracket: char, empty: bool) {
+        self.level -= 1;
+        if !empty && let Some(indent) = self.indent {
+            self.out.push('\n');
+            self.out
+                .extend(std::iter::repeat_n(' ', indent * self.level));
+        }
+        self.out.push(bracket);
+    }
+
+    fn key(&mut self, key: &str, first: bool) {
+        self.separator(first);
+        write_ascii_string(&mut self.out, key.chars().map(u32::from));
+        self.out.push_str(": ");
+    }
+
+    /// A `{"_type": tag, ...}` object whose members `body` writes after
+    /// the tag.
+    fn tagged(&mut self, tag: &str, body: impl FnOnce(&mut Self) -> PyResult<()>) -> PyResult<()> {
+        self.open('{');
+        self.key("_type", true);
+        write_ascii_string(&mut self.out, tag.chars().map(u32::from));
+        body(self)?;
+        self.close('}', false);
+        Ok(())
+    }
+
+    fn items(&mut self, items: impl Iterator<Item = Bound<'py, PyAny>>) -> PyResult<()> {
+        let _guard = RecursionGuard::enter(self.py)?;
+        self.open('[');
+        let mut empty = true;
+        for item in items {
+            self.separator(empty);
+            empty = false;
+            self.value(&item)?;
+        }
+        self.close(']', empty);
+        Ok(())
+    }
+
+    pub(crate) fn value(&mut self, value: &Bound<'py, PyAny>) -> PyResult<()> {
+        let py = self.py;
+        if value.is_none() {
+            self.out.push_str("null");
+        } else if let Ok(flag) = value.cast::<PyBool>() {
+            self.out
+                .push_str(if flag.is_true() { "true" } else { "false" });
+        } else if let Ok(string) = value.cast::<PyString>() {
+            write_py_string(&mut self.out, string)?;
+        } else if let Ok(int) = value.cast::<PyInt>() {
+            match int.extract::<i64>() {
+                Ok(small) => write!(self.out, "{small}").unwrap(),
+                // `int.__repr__`, not a subclass's `__str__`.
+                Err(_) => self
+                    .out
+                    .push_str(&py.get_type::<PyInt>().call1((int,))?.repr()?.to_string()),
+            }
+        } else if let Ok(float) = value.cast::<PyFloat>() {
+            self.float(float.value())?;
+        } else if let Ok(list) = value.cast::<PyList>() {
+            self.items(list.iter())?;
+        } else if self.walker.is_root(value) {
+            self.node(value)?;
+        } else if let Ok(bytes) = value.cast::<PyBytes>() {
+            self.tagged("bytes", |this| {
+                this.key("base64", false);
+                this.out.push('"');
+                base64_encode(&mut this.out, bytes.as_bytes());
+                this.out.push('"');
+                Ok(())
+            })?;
+        } else if let Ok(complex) = value.cast::<PyComplex>() {
+            let (real, imag) = (complex.real(), complex.imag());
+            self.tagged("complex", |this| {
+                this.key("real", false);
+                this.float(real)?;
+                this.key("imag", false);
+                this.float(imag)
+            })?;
+        } else if value.is(py.Ellipsis()) {
+            self.tagged("Ellipsis", |_| Ok(()))?;
+        } else if let Ok(tuple) = value.cast::<PyTuple>() {
+            self.tagged("tuple", |this| {
+                this.key("elts", false);
+                this.items(tuple.iter())
+            })?;
+        } else if let Ok(set) = value.cast::<PyFrozenSet>() {
+            self.tagged("frozenset", |this| {
+                this.key("elts", false);
+                this.items(set.iter())
+            })?;
+        } else {
+            return Err(PyTypeError::new_err(format!(
+                "Object of type {} is not JSON serializable",
+                value.get_type().name()?
+            )));
+        }
+        Ok(())
+    }
+
+    /// Finite floats as `float.__repr__` spells them (what `json.dumps`
+    /// emits); the rest as tagged strings, since JSON has no literal.
+    fn float(&mut self, value: f64) -> PyResult<()> {
+        if value.is_finite() {
+            let repr = PyFloat::new(self.py, value).repr()?;
+            self.out.push_str(repr.to_str()?);
+            return Ok(());
+        }
+        let spelled = if value.is_nan() {
+            "nan"
+        } else if value > 0.0 {
+            "inf"
+        } else {
+            "-inf"
+        };
+        self.tagged("float", |this| {
+            this.key("value", false);
+            this.out.push('"');
+            this.out.push_str(spelled);
+            this.out.push('"');
+            Ok(())
+        })
+    }
+
+    fn node(&mut self, node: &Bound<'py, PyAny>) -> PyResult<()> {
+        let _guard = RecursionGuard::enter(self.py)?;
+        let names = self.fields.names(node)?;
+        let attributes = if self.include_positions {
+            self.fields.attributes(node)?
+        } else {
+            Default::default()
+        };
+        let type_name = node.get_type().name()?;
+        self.tagged(type_name.to_str()?, |this| {
+            for name in names.iter().chain(attributes.iter()) {
+                if let Some(value) = this.fields.get(node, name)? {
+                    this.key(name.to_str()?, false);
+                    this.value(&value)?;
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Write a Python `str` as a JSON string literal. Goes by code point
+/// rather than through UTF-8, which lone surrogates can't be encoded in.
+fn write_py_string(out: &mut String, string: &Bound<'_, PyString>) -> PyResult<()> {
+    // SAFETY: the data is only read while `string` is borrowed.
+    match unsafe { string.data()? } {
+        PyStringData::Ucs1(data) => write_ascii_string(out, data.iter().map(|&c| u32::from(c))),
+        PyStringData::Ucs2(data) => write_ascii_string(out, data.iter().map(|&c| u32::from(c))),
+        PyStringData::Ucs4(data) => write_ascii_string(out, data.iter().copied()),
+    }
+    Ok(())
+}
+
+/// `json.dumps`-style string literal with `ensure_ascii`: printable
+/// ASCII as is, the usual short escapes, everything else as `\uXXXX`
+/// (astral code points as a surrogate pair).
+fn write_ascii_string(out: &mut String, code_points: impl Iterator<Item = u32>) {
+    out.push('"');
+    for c in code_points {
+        match c {
+            0x22 => out.push_str("\\\""),
+            0x5c => out.push_str("\\\\"),
+            0x0a => out.push_str("\\n"),
+            0x0d => out.push_str("\\r"),
+            0x09 => out.push_str("\\t"),
+            0x08 => out.push_str("\\b"),
+            0x0c => out.push_str("\\f"),
+            0x20..=0x7e => out.push(c as u8 as char),
+            0x10000.. => {
+                let c = c - 0x10000;
+                write!(
+                    out,
+                    "\\u{:04x}\\u{:04x}",
+                    0xd800 + (c >> 10),
+                    0xdc00 + (c & 0x3ff)
+                )
+                .unwrap();
+            }
+            _ => write!(out, "\\u{c:04x}").unwrap(),
+        }
+    }
+    out.push('"');
+}
+
+/// Standard, padded base64 (RFC 4648 §4).
+fn base64_encode(out: &mut String, bytes: &[u8]) {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from(b[0]) << 16 | u32::from(b[1]) << 8 | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+}
+
+/// One step of the path from the document root to the value being read,
+/// for error messages.
+enum Segment<'py> {
+    Key(Bound<'py, PyString>),
+    Index(usize),
+}
+
+/// A stdlib node class `from_json` can instantiate.
+struct NodeClass<'py> {
+    class: Bound<'py, PyType>,
+    /// `_fields` and `_attributes`, read the first time the class is used.
+    names: Option<HashSet<String>>,
+}
+
+/// Recursive-descent parser for `from_json`, building Python objects as
+/// it goes: JSON objects are collected into a dict and then turned into
+/// a node or a tagged value once complete, so children always exist
+/// before their parent is constructed.
+pub(crate) struct JsonReader<'t, 'py> {
+    py: Python<'py>,
+    text: &'t str,
+    pos: usize,
+    path: Vec<Segment<'py>>,
+    classes: HashMap<String, NodeClass<'py>>,
+}
+
+impl<'t, 'py> JsonReader<'t, 'py> {
+    pub(crate) fn new(py: Python<'py>, text: &'t str) -> PyResult<Self> {
+        let classes = with_field_table(py, |field_table| {
+            let mut classes = HashMap::with_capacity(field_table.vocabulary.len());
+            for class in &field_table.vocabulary {
+                let class = class.bind(py).clone();
+                classes
+                    .entry(class.name()?.to_string())
+                    .or_insert(NodeClass { class, names: None });
+            }
+            Ok(classes)
+        })?;
+        Ok(Self {
+            py,
+            text,
+            pos: 0,
+            path: Vec::new(),
+            classes,
+        })
+    }
+
+    /// Parse the whole text as one value.
+    pub(crate) fn read(mut self) -> PyResult<Bound<'py, PyAny>> {
+        self.skip_whitespace();
+        let value = self.value()?;
+        self.skip_whitespace();
+        if self.pos != self.text.len() {
+            return Err(self.syntax_error("end of input"));
+        }
+        Ok(value)
+    }
+
+    /// `$.body[0].value`-style rendering of [`path`](Self::path).
+    fn path(&self) -> String {
+        let mut path = String::from("$");
+        for segment in &self.path {
+            match segment {
+                Segment::Index(index) => write!(path, "[{index}]").unwrap(),
+                Segment::Key(key) => {
+                    let key = key.to_string_lossy();
+                    if is_identifier(&key) {
+                        write!(path, ".{key}").unwrap();
+                    } else {
+                        path.push('[');
+                        write_ascii_string(&mut path, key.chars().map(u32::from));
+                        path.push(']');
+                    }
+                }
+            }
+        }
+        path
+    }
+
+    /// `ValueError` for a well-formed document that doesn't describe a
+    /// tree, located by its JSON path.
+    fn error(&self, message: impl std::fmt::Display) -> PyErr {
+        PyValueError::new_err(format!("from_json(): {message} at {}", self.path()))
+    }
+
+    /// `ValueError` for malformed JSON, located by path and by line and
+    /// column (both 1-based, like `json.JSONDecodeError`).
+    fn syntax_error(&self, expected: &str) -> PyErr {
+        let before = &self.text.as_bytes()[..self.pos];
+        let line_start = before
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1);
+        let line = before.iter().filter(|&&b| b == b'\n').count() + 1;
+        let column = String::from_utf8_lossy(&before[line_start..])
+            .chars()
+            .count()
+            + 1;
+        let found = match self.text[self.pos..].chars().next() {
+            Some(c) => format!("{c:?}"),
+            None => "end of input".to_owned(),
+        };
+        PyValueError::new_err(format!(
+            "from_json(): expected {expected}, found {found} at {} (line {line}, column {column})",
+            self.path(),
+        ))
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8, expected: &str) -> PyResult<()> {
+        if self.peek() != Some(byte) {
+            return Err(self.syntax_error(expected));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn value(&mut self) -> PyResult<Bound<'py, PyAny>> {
+        let py = self.py;
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => Ok(self.array()?.into_any()),
+            Some(b'"') => Ok(self.string()?.into_any()),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(b't') => self.literal("true", PyBool::new(py, true).to_owned().into_any()),
+            Some(b'f') => self.literal("false", PyBool::new(py, false).to_owned().into_any()),
+            Some(b'n') => self.literal("null", py.None().into_bound(py)),
+            _ => Err(self.syntax_error("a value")),
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Bound<'py, PyAny>) -> PyResult<Bound<'py, PyAny>> {
+        if !self.text[self.pos..].starts_with(word) {
+            return Err(self.syntax_error(&format!("'{word}'")));
+        }
+        self.pos += word.len();
+        Ok(value)
+    }
+
+    fn array(&mut self) -> PyResult<Bound<'py, PyList>> {
+        let _guard = RecursionGuard::enter(self.py)?;
+        let list = PyList::empty(self.py);
+        self.pos += 1;
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(list);
+        }
+        loop {
+            self.path.push(Segment::Index(list.len()));
+            list.append(self.value()?)?;
+            self.path.pop();
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => {
+                    self.pos += 1;
+                    self.skip_whitespace();
+                }
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(list);
+                }
+                _ => return Err(self.syntax_error("',' or ']'")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> PyResult<Bound<'py, PyAny>> {
+        let _guard = RecursionGuard::enter(self.py)?;
+        let members = PyDict::new(self.py);
+        self.pos += 1;
+        self.skip_whitespace();
+        if self.peek() != Some(b'}') {
+            loop {
+                if self.peek() != Some(b'"') {
+                    return Err(self.syntax_error("a string key"));
+                }
+                let key = self.string()?;
+                self.skip_whitespace();
+                self.expect(b':', "':'")?;
+                self.skip_whitespace();
+                self.path.push(Segment::Key(key.clone()));
+                members.set_item(key, self.value()?)?;
+                self.path.pop();
+                self.skip_whitespace();
+                match self.peek() {
+                    Some(b',') => {
+                        self.pos += 1;
+                        self.skip_whitespace();
+                    }
+                    Some(b'}') => break,
+                    _ => return Err(self.syntax_error("',' or '}'")),
+                }
+            }
+        }
+        self.pos += 1;
+        self.tagged(&members)
+    }
+
+    /// Turn a parsed object into the node or value its `"_type"` names.
+    /// `members` is consumed: `_type` and the tagged values' own keys
+    /// are removed, and what is left of a node's becomes its kwargs.
+    fn tagged(&mut self, members: &Bound<'py, PyDict>) -> PyResult<Bound<'py, PyAny>> {
+        let py = self.py;
+        let Some(tag) = members.get_item(intern!(py, "_type"))? else {
+            return Err(self.error("object has no \"_type\""));
+        };
+        members.del_item(intern!(py, "_type"))?;
+        let Ok(tag) = tag.cast_into::<PyString>() else {
+            return Err(self.error("\"_type\" is not a string"));
+        };
+        let value = match &*tag.to_cow()? {
+            "bytes" => {
+                let encoded = self.member(members, "bytes", "base64")?;
+                let decoded = encoded
+                    .cast::<PyString>()
+                    .ok()
+                    .and_then(|encoded| encoded.to_str().ok())
+                    .and_then(base64_decode);
+                match decoded {
+                    Some(decoded) => PyBytes::new(py, &decoded).into_any(),
+                    None => return Err(self.error("bytes \"base64\" is not valid base64")),
+                }
+            }
+            "complex" => {
+                let real = self.member(members, "complex", "real")?;
+                let imag = self.member(members, "complex", "imag")?;
+                match (real.extract(), imag.extract()) {
+                    (Ok(real), Ok(imag)) => PyComplex::from_doubles(py, real, imag).into_any(),
+                    _ => return Err(self.error("complex parts must be numbers")),
+                }
+            }
+            "Ellipsis" => py.Ellipsis().into_bound(py),
+            "float" => {
+                let spelled = self.member(members, "float", "value")?;
+                let value = match spelled.cast::<PyString>().map(|s| s.to_cow()) {
+                    Ok(Ok(spelled)) if spelled == "inf" => f64::INFINITY,
+                    Ok(Ok(spelled)) if spelled == "-inf" => f64::NEG_INFINITY,
+                    Ok(Ok(spelled)) if spelled == "nan" => f64::NAN,
+                    _ => {
+                        return Err(
+                            self.error("float \"value\" must be \"inf\", \"-inf\" or \"nan\"")
+                        );
+                    }
+                };
+                PyFloat::new(py, value).into_any()
+            }
+            "tuple" => {
+                let elts = self.member(members, "tuple", "elts")?;
+                match elts.cast::<PyList>() {
+                    Ok(elts) => elts.to_tuple().into_any(),
+                    Err(_) => return Err(self.error("tuple \"elts\" is not an array")),
+                }
+            }
+            "frozenset" => {
+                let elts = self.member(members, "frozenset", "elts")?;
+                match elts.cast::<PyList>() {
+                    Ok(elts) => PyFrozenSet::new(py, elts)?.into_any(),
+                    Err(_) => return Err(self.error("frozenset \"elts\" is not an array")),
+                }
+            }
+            name => return self.node(name, members),
+        };
+        if let Some((key, _)) = members.iter().next() {
+            self.path.push(Segment::Key(key.cast_into()?));
+            return Err(self.error(format_args!("unexpected key in {tag} object")));
+        }
+        Ok(value)
+    }
+
+    /// Remove and return `members[key]` of a `tag` value.
+    fn member(
+        &self,
+        members: &Bound<'py, PyDict>,
+        tag: &str,
+        key: &str,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let Some(value) = members.get_item(key)? else {
+            return Err(self.error(format_args!("{tag} object has no \"{key}\"")));
+        };
+        members.del_item(key)?;
+        Ok(value)
+    }
+
+    /// `ast.<name>(**members)`, after checking that every member is one
+    /// of the class's `_fields` or `_attributes`.
+    fn node(&mut self, name: &str, members: &Bound<'py, PyDict>) -> PyResult<Bound<'py, PyAny>> {
+        let Some(node_class) = self.classes.get_mut(name) else {
+            return Err(self.error(format_args!("unknown \"_type\" {name:?}")));
+        };
+        if node_class.names.is_none() {
+            let class = &node_class.class;
+            let mut names = HashSet::new();
+            for attribute in [intern!(self.py, "_fields"), intern!(self.py, "_attributes")] {
+                for field in class.getattr(attribute)?.try_iter()? {
+                    names.insert(field?.extract::<String>()?);
+                }
+            }
+            node_class.names = Some(names);
+        }
+        let (class, names) = (&node_class.class, node_class.names.as_ref().unwrap());
+        for key in members.keys() {
+            let key = key.cast_into::<PyString>()?;
+            if !names.contains(&*key.to_string_lossy()) {
+                let message = format!("{name} has no field {:?}", key.to_string_lossy());
+                self.path.push(Segment::Key(key));
+                return Err(self.error(message));
+            }
+        }
+        class.call((), Some(members))
+    }
+
+    fn string(&mut self) -> PyResult<Bound<'py, PyString>> {
+        self.pos += 1;
+        let start = self.pos;
+        // Fast path: no escapes, so the literal is the string.
+        while let Some(byte) = self.peek() {
+            match byte {
+                b'"' => {
+                    self.pos += 1;
+                    return Ok(PyString::new(self.py, &self.text[start..self.pos - 1]));
+                }
+                b'\\' => break,
+                0..0x20 => return Err(self.syntax_error("'\"'")),
+                _ => self.pos += 1,
+            }
+        }
+        // Escapes can spell lone surrogates, which a Rust string can't
+        // hold, so collect code points instead.
+        let mut code_points: Vec<u32> = self.text[start..self.pos].chars().map(u32::from).collect();
+        loop {
+            match self.peek() {
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escaped = match self.peek() {
+                        Some(b'"') => 0x22,
+                        Some(b'\\') => 0x5c,
+                        Some(b'/') => 0x2f,
+                        Some(b'b') => 0x08,
+                        Some(b'f') => 0x0c,
+                        Some(b'n') => 0x0a,
+                        Some(b'r') => 0x0d,
+                        Some(b't') => 0x09,
+                        Some(b'u') => {
+                            self.pos += 1;
+                            let mut c = self.hex4()?;
+                            // A surrogate pair is one code point, as in `json.loads`.
+                            let low_start = self.pos;
+                            if (0xd800..0xdc00).contains(&c)
+                                && self.text[self.pos..].starts_with("\\u")
+                            {
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                if (0xdc00..0xe000).contains(&low) {
+                                    c = 0x10000 + ((c - 0xd800) << 10) + (low - 0xdc00);
+                                } else {
+                                    self.pos = low_start;
+                                }
+                            }
+                            code_points.push(c);
+                            continue;
+                        }
+                        _ => return Err(self.syntax_error("an escape sequence")),
+                    };
+                    code_points.push(escaped);
+                    self.pos += 1;
+                }
+                Some(0..0x20) | None => return Err(self.syntax_error("'\"'")),
+                Some(_) => {
+                    let c = self.text[self.pos..].chars().next().unwrap();
+                    code_points.push(u32::from(c));
+                    self.pos += c.len_utf8();
+                }
+            }
+        }
+        self.pos += 1;
+        // SAFETY: `code_points` holds `len` UCS-4 values below 0x110000.
+        unsafe {
+            Bound::from_owned_ptr_or_err(
+                self.py,
+                ffi::PyUnicode_FromKindAndData(
+                    ffi::PyUnicode_4BYTE_KIND as _,
+                    code_points.as_ptr().cast(),
+                    code_points.len() as ffi::Py_ssize_t,
+                ),
+            )
+            .map(|string| string.cast_into_unchecked())
+        }
+    }
+
+    /// The four hex digits of a `\u` escape.
+    fn hex4(&mut self) -> PyResult<u32> {
+        let digits = self.text.get(self.pos..self.pos + 4);
+        let Some(digits) = digits.filter(|d| d.bytes().all(|b| b.is_ascii_hexdigit())) else {
+            return Err(self.syntax_error("four hex digits"));
+        };
+        self.pos += 4;
+        Ok(u32::from_str_radix(digits, 16).unwrap())
+    }
+
+    fn number(&mut self) -> PyResult<Bound<'py, PyAny>> {
+        let start = self.pos;
+        let skip_digits = |this: &mut Self| -> PyResult<()> {
+            if !matches!(this.peek(), Some(b'0'..=b'9')) {
+                return Err(this.syntax_error("a digit"));
+            }
+            while let Some(b'0'..=b'9') = this.peek() {
+                this.pos += 1;
+            }
+            Ok(())
+        };
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        if self.peek() == Some(b'0') {
+            self.pos += 1;
+        } else {
+            skip_digits(self)?;
+        }
+        let mut is_float = false;
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            skip_digits(self)?;
+            is_float = true;
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            self.pos += 1;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.pos += 1;
+            }
+            skip_digits(self)?;
+            is_float = true;
+        }
+        let literal = &self.text[start..self.pos];
+        if is_float {
+            // Rust's parser rounds correctly, like `float()`.
+            return Ok(PyFloat::new(self.py, literal.parse().unwrap()).into_any());
+        }
+        match literal.parse::<i64>() {
+            Ok(value) => Ok(value.into_pyobject(self.py)?.into_any()),
+            Err(_) => self.py.get_type::<PyInt>().call1((literal,)),
+        }
+    }
+}
+
+/// Inverse of [`base64_encode`]; `None` unless `text` is canonical,
+/// padded base64.
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let sextet = |byte: u8| match byte {
+        b'A'..=b'Z' => Some(u32::from(byte - b'A')),
+        b'a'..=b'z' => Some(u32::from(byte - b'a') + 26),
+        b'0'..=b'9' => Some(u32::from(byte - b'0') + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    };
+    let bytes = text.as_bytes();
+    if !bytes.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(bytes.len() / 4 * 3);
+    let last = (bytes.len() / 4).saturating_sub(1);
+    for (i, chunk) in bytes.chunks(4).enumerate() {
+        let padding = if i == last {
+            chunk.iter().rev().take_while(|&&b| b == b'=').count()
+        } else {
+            0
+        };
+        if padding > 2 {
+            return None;
+        }
+        let mut n = 0;
+        for &byte in &chunk[..4 - padding] {
+            n = n << 6 | sextet(byte)?;
+        }
+        n <<= 6 * padding;
+        out.extend_from_slice(&n.to_be_bytes()[1..4 - padding]);
+    }
+    Some(out)
+}
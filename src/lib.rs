@@ -3,27 +3,46 @@
 //! The public API exposes two traversal variants:
 //!
 //! - [`walk_dfs`] — strict depth-first pre-order.
-//! - [`walk_unordered`] — faster; same set of nodes, implementation-defined
-//!   order. Uses batched prefetching to hide cache-miss latency on the
-//!   scattered `PyDictKeysObject` loads that dominate the DFS profile.
+//! - [`walk_unordered`] — same set of nodes, implementation-defined
+//!   order. Uses software-pipelined prefetching to hide cache-miss
+//!   latency on the scattered `PyDictKeysObject` loads, which only pays
+//!   off on trees too large for the cache.
 //!
 //! `ast.walk` makes no ordering guarantee, so `walk_unordered` is a drop-in
 //! replacement wherever order doesn't matter.
 
+#[cfg(feature = "arrow")]
+mod arrow;
+mod ast_dump;
+mod binary;
+mod compare;
+mod dot;
+mod encode;
+mod fields;
+mod json;
+mod merkle;
+mod prefetch;
 mod pydict;
+mod sexpr;
 
 use std::cell::{Cell, RefCell};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::HashSet;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
-use pyo3::exceptions::PyDeprecationWarning;
+use pyo3::exceptions::{PyAttributeError, PyDeprecationWarning, PyTypeError, PyValueError};
 use pyo3::ffi::{self, PyListObject, PyObject, PyTypeObject};
-use pyo3::types::{PyList, PyModule, PyType};
-use pyo3::{PyTypeInfo, prelude::*};
+use pyo3::types::{PyBool, PyBytes, PyDict, PyList, PyModule, PyString, PyTuple, PyType};
+use pyo3::{PyTypeInfo, create_exception, intern, prelude::*};
+
+use crate::prefetch::prefetch_l1;
 
 /// Open-addressed, direct-mapped lookup from `*mut PyTypeObject` to an
 /// AST-classification code. Specialized for the ~130 `ast.AST`
-/// subclasses; populated once per thread at first walk and then
-/// read-only on the hot path.
+/// subclasses; populated once per thread at first walk, then extended
+/// lazily whenever the walk meets an AST subclass defined after that
+/// snapshot (see [`FieldTable::insert_late_type`]).
 ///
 /// **Value encoding** (chosen to collapse the two hot-path predicates
 /// "is this an AST subclass?" and "how many `_fields` does it have?"
@@ -37,68 +56,184 @@ use pyo3::{PyTypeInfo, prelude::*};
 ///   lets a single `table.get(t) > 0` check replace the old
 ///   `issubclass_of_ast` walk over `tp_base`, and `table.get(t) - 1`
 ///   give the `_fields` count.
+/// - [`FIELDS_DYNAMIC`] — AST type whose instances carry their own
+///   `_fields`; routed to the slow `getattr`-based path.
 ///
 /// Stdlib AST types top out at 7 `_fields`, so `u8` is more than
-/// enough; the table stays at 256 bytes of values + 2 KB of keys = one
-/// L1-resident data structure for the whole walk.
-///
-/// Layout: parallel `keys` and `values` arrays of `SIZE` slots each.
-/// Keys are u64 (pointer as integer); empty slot is `key == 0`. Values
-/// are u8. Index function: `(ptr >> 4) & (SIZE - 1)` — type objects
-/// are allocator-aligned, so shifting right by 4 drops the alignment
-/// zeros and gives well-distributed indices across our ~130 live
-/// types.
-const FIELD_TABLE_SIZE: usize = 256; // power of two, load factor ~0.5
-const FIELD_TABLE_MASK: usize = FIELD_TABLE_SIZE - 1;
+/// enough; at its initial size the table is 256 bytes of values + 2 KB
+/// of keys = one L1-resident data structure for the whole walk.
+///
+/// Layout: parallel `keys` and `values` arrays of `keys.len()` slots
+/// (always a power of two). Keys are u64 (pointer as integer); empty
+/// slot is `key == 0`. Values are u8. Index function: `(ptr >> 4) &
+/// mask` — type objects are allocator-aligned, so shifting right by 4
+/// drops the alignment zeros and gives well-distributed indices across
+/// our ~130 live types.
+///
+/// The table doubles whenever an insert would push the load factor past
+/// 0.5, so there is always at least one empty slot and both probe loops
+/// are guaranteed to terminate — even for projects that define hundreds
+/// of custom `ast.AST` subclasses.
+///
+/// Each slot also carries a `u16` type code for [`encode_tree`]: the
+/// index into `vocabulary`, the stdlib node classes in the order the
+/// prebuild inserts them. That order only depends on the Python
+/// version, so codes are stable across threads and processes. Other
+/// AST types share the code of their nearest stdlib ancestor.
+///
+/// Keying by address is only sound while the type is alive: a class
+/// that gets garbage-collected could have its memory reused by a new
+/// class, which would silently inherit the stale entry. Every real type
+/// inserted through [`insert_type`](Self::insert_type) is therefore
+/// pinned with a strong reference while it is in the table. The
+/// prebuilt types stay for the table's lifetime; types inserted later
+/// are dropped again, all at once, when there are more than
+/// [`MAX_LATE_TYPES`] of them (see [`with_field_table`]), so a program
+/// that keeps creating AST classes doesn't keep every one of them alive.
+const FIELD_TABLE_INITIAL_SIZE: usize = 256; // power of two, load factor ~0.5
 
 struct FieldTable {
-    keys: [u64; FIELD_TABLE_SIZE],
-    values: [u8; FIELD_TABLE_SIZE],
+    keys: Box<[u64]>,
+    values: Box<[u8]>,
+    mask: usize,
+    len: usize,
+    codes: Box<[u16]>,
+    pinned: Vec<Py<PyType>>,
+    /// How many of `pinned` the prebuild inserted; the rest are late.
+    prebuilt: usize,
+    vocabulary: Vec<Py<PyType>>,
 }
 
+/// Late-inserted types a [`FieldTable`] pins before it lets go of them.
+const MAX_LATE_TYPES: usize = 1024;
+
 impl FieldTable {
     fn new() -> Self {
+        Self::with_size(FIELD_TABLE_INITIAL_SIZE)
+    }
+
+    fn with_size(size: usize) -> Self {
+        debug_assert!(size.is_power_of_two(), "table size must be a power of two");
         Self {
-            keys: [0; FIELD_TABLE_SIZE],
-            values: [0; FIELD_TABLE_SIZE],
+            keys: vec![0; size].into_boxed_slice(),
+            values: vec![0; size].into_boxed_slice(),
+            mask: size - 1,
+            len: 0,
+            codes: vec![0; size].into_boxed_slice(),
+            pinned: Vec::new(),
+            prebuilt: 0,
+            vocabulary: Vec::new(),
         }
     }
 
-    /// Store an AST type with its `_fields` length. The value written
-    /// is `n_fields + 1`; see the struct-level docs for the encoding.
-    fn insert_ast(&mut self, ptr: *mut PyTypeObject, n_fields: u8) {
-        let key = ptr as u64;
-        debug_assert!(key != 0, "null type pointer");
-        debug_assert!(n_fields < u8::MAX, "n_fields would overflow encoding");
-        let encoded = n_fields + 1;
-        let mut idx = ((key >> 4) as usize) & FIELD_TABLE_MASK;
-        // Linear probe until we find the key or an empty slot. Load
-        // factor 0.5 keeps the expected probe length ≲ 2.
+    /// Index of `key`'s slot, or of the empty slot where it would go.
+    /// Linear probe; load factor ≤ 0.5 keeps the expected probe length
+    /// ≲ 2 and guarantees an empty slot exists.
+    fn slot_of(&self, key: u64) -> usize {
+        let mut idx = ((key >> 4) as usize) & self.mask;
         loop {
             let k = self.keys[idx];
-            if k == key {
-                self.values[idx] = encoded;
-                return;
+            if k == key || k == 0 {
+                return idx;
             }
-            if k == 0 {
-                self.keys[idx] = key;
-                self.values[idx] = encoded;
-                return;
+            idx = (idx + 1) & self.mask;
+        }
+    }
+
+    /// Store an AST type with its encoded value (see [`type_encoding`]
+    /// and the struct-level docs). Returns the type's slot.
+    fn insert(&mut self, ptr: *mut PyTypeObject, encoded: u8) -> usize {
+        let key = ptr as u64;
+        debug_assert!(key != 0, "null type pointer");
+        debug_assert!(encoded != 0, "0 is reserved for non-AST types");
+        let mut idx = self.slot_of(key);
+        if self.keys[idx] == 0 {
+            if (self.len + 1) * 2 > self.keys.len() {
+                self.grow();
+                idx = self.slot_of(key);
+            }
+            self.keys[idx] = key;
+            self.len += 1;
+        }
+        self.values[idx] = encoded;
+        idx
+    }
+
+    /// Insert a live AST type object and pin it (see the struct-level
+    /// docs). Returns the encoded value.
+    fn insert_type(&mut self, t: &Bound<'_, PyType>) -> u8 {
+        let encoded = type_encoding(t);
+        let code = self.type_code(t);
+        let slot = self.insert(t.as_type_ptr(), encoded);
+        self.codes[slot] = code;
+        self.pinned.push(t.clone().unbind());
+        encoded
+    }
+
+    /// Type code for `t`: its `vocabulary` index, or else the code of
+    /// the first class in its MRO that the table already holds. `ast.AST`
+    /// is always there, so every AST type gets a code.
+    fn type_code(&self, t: &Bound<'_, PyType>) -> u16 {
+        if let Some(code) = self.vocabulary.iter().position(|v| v.is(t)) {
+            return code as u16;
+        }
+        for base in t.mro().iter().skip(1) {
+            if let Some(slot) = self.slot_for(base.as_ptr().cast()) {
+                return self.codes[slot];
+            }
+        }
+        0
+    }
+
+    /// Double the slot count and rehash every entry.
+    #[cold]
+    #[inline(never)]
+    fn grow(&mut self) {
+        let mut bigger = Self::with_size(self.keys.len() * 2);
+        for (&key, &value) in self.keys.iter().zip(self.values.iter()) {
+            if key != 0 {
+                let idx = bigger.slot_of(key);
+                bigger.keys[idx] = key;
+                bigger.values[idx] = value;
+                bigger.codes[idx] = self.codes[self.slot_of(key)];
             }
-            idx = (idx + 1) & FIELD_TABLE_MASK;
         }
+        bigger.len = self.len;
+        bigger.pinned = std::mem::take(&mut self.pinned);
+        bigger.prebuilt = self.prebuilt;
+        bigger.vocabulary = std::mem::take(&mut self.vocabulary);
+        *self = bigger;
     }
 
-    /// Raw encoded value: `0` if not an AST type, `n_fields + 1`
-    /// otherwise. Hot path uses this directly so "is AST?" (`> 0`) and
+    /// Drop every type inserted after the prebuild, unpinning it. Those
+    /// still in use are classified again the next time a walk meets
+    /// them.
+    #[cold]
+    #[inline(never)]
+    fn forget_late_types(&mut self) {
+        let mut fresh = Self::with_size(self.keys.len());
+        for t in &self.pinned[..self.prebuilt] {
+            let slot = self.slot_for(t.as_ptr().cast()).unwrap();
+            let fresh_slot = fresh.insert(t.as_ptr().cast(), self.values[slot]);
+            fresh.codes[fresh_slot] = self.codes[slot];
+        }
+        fresh.prebuilt = self.prebuilt;
+        fresh.vocabulary = std::mem::take(&mut self.vocabulary);
+        fresh.pinned = std::mem::take(&mut self.pinned);
+        fresh.pinned.truncate(self.prebuilt);
+        *self = fresh;
+    }
+
+    /// Raw encoded value: `0` if not an AST type, `n_fields + 1` or
+    /// [`FIELDS_DYNAMIC`] otherwise. Hot path uses this directly so "is AST?" (`> 0`) and
     /// "how many fields?" (`- 1`) share a single load.
     #[inline(always)]
     fn lookup(&self, ptr: *mut PyTypeObject) -> u8 {
         let key = ptr as u64;
-        let mut idx = ((key >> 4) as usize) & FIELD_TABLE_MASK;
+        let mut idx = ((key >> 4) as usize) & self.mask;
         loop {
-            // SAFETY: `idx` is always masked to `FIELD_TABLE_MASK`, so it
-            // stays within the fixed-size array.
+            // SAFETY: `idx` is always masked to `self.mask`, which is
+            // `keys.len() - 1`, so it stays within both slices.
             let k = unsafe { *self.keys.get_unchecked(idx) };
             if k == key {
                 return unsafe { *self.values.get_unchecked(idx) };
@@ -106,11 +241,52 @@ impl FieldTable {
             if k == 0 {
                 return 0;
             }
-            idx = (idx + 1) & FIELD_TABLE_MASK;
+            idx = (idx + 1) & self.mask;
+        }
+    }
+
+    /// Slot holding `ptr`, if the table has it. Slots are stable until
+    /// the next insert, so callers can key small per-type side tables by
+    /// them.
+    fn slot_for(&self, ptr: *mut PyTypeObject) -> Option<usize> {
+        let idx = self.slot_of(ptr as u64);
+        (self.keys[idx] == ptr as u64).then_some(idx)
+    }
+
+    /// Resolve a [`lookup`](Self::lookup) miss: an `ast.AST` subclass is
+    /// inserted and its fresh encoding returned. This is what keeps node
+    /// classes defined after the first walk on a thread (late imports,
+    /// DSL node types created at runtime) from being treated as leaves.
+    /// Non-AST types are not cached: only the walk root can reach here
+    /// without having passed `issubclass_of_ast`, so a negative entry
+    /// would never pay off.
+    #[cold]
+    #[inline(never)]
+    fn insert_late_type(
+        &mut self,
+        py: Python<'_>,
+        ptr: *mut PyTypeObject,
+        base_ast_type: *mut PyTypeObject,
+    ) -> u8 {
+        if unsafe { ffi::PyType_IsSubtype(ptr, base_ast_type) } == 0 {
+            return 0;
         }
+        // SAFETY: `ptr` is the type of a live object on the walk stack,
+        // so it is a valid type object that outlives this handle.
+        let t = unsafe {
+            Bound::from_borrowed_ptr(py, ptr.cast::<PyObject>()).cast_into_unchecked::<PyType>()
+        };
+        self.insert_type(&t)
     }
 }
 
+/// [`FieldTable`] value for AST types that set
+/// `_fast_walk_dynamic_fields = True`: `_fields` is read from each
+/// instance instead of once per type. Covers adapter libraries that
+/// assign `_fields` per instance and generic node classes whose field
+/// list varies from node to node.
+const FIELDS_DYNAMIC: u8 = u8::MAX;
+
 /// Reverse iterator over the first `limit` values of a Python dict whose
 /// keys are all strings — the layout used by instance `__dict__`s. Reads
 /// the `PyDictKeysObject` entry table directly and skips null (deleted)
@@ -178,18 +354,60 @@ fn get_instance_dict_fast(obj: *mut PyObject) -> Option<*mut PyObject> {
     }
 }
 
-/// L1 prefetch hint. No-op on non-x86_64 targets — the Python extension
-/// builds and runs identically without it, just without the cache-miss
-/// hiding that benefits `walk_unordered`.
-#[inline(always)]
-unsafe fn prefetch_l1(ptr: *const u8) {
-    #[cfg(target_arch = "x86_64")]
-    unsafe {
-        std::arch::x86_64::_mm_prefetch(ptr as *const i8, std::arch::x86_64::_MM_HINT_T0);
+/// Holds one level of `Py_EnterRecursiveCall`, so that the recursive
+/// serializers raise `RecursionError` on a pathologically deep tree
+/// instead of overflowing the C stack — the guard the `json` module uses.
+struct RecursionGuard;
+
+impl RecursionGuard {
+    fn enter(py: Python<'_>) -> PyResult<Self> {
+        if unsafe { ffi::Py_EnterRecursiveCall(c" while converting an AST".as_ptr()) } != 0 {
+            return Err(PyErr::fetch(py));
+        }
+        Ok(Self)
     }
-    #[cfg(not(target_arch = "x86_64"))]
-    {
-        let _ = ptr;
+}
+
+impl Drop for RecursionGuard {
+    fn drop(&mut self) {
+        unsafe { ffi::Py_LeaveRecursiveCall() };
+    }
+}
+
+/// UTF-8 bytes of `string`, lone surrogates included (`surrogatepass`).
+fn utf8<'a>(string: &'a Bound<'_, PyString>) -> PyResult<std::borrow::Cow<'a, [u8]>> {
+    if let Ok(text) = string.to_str() {
+        return Ok(text.as_bytes().into());
+    }
+    // SAFETY: `string` is a live `str`; the result is an owned `bytes`.
+    let encoded = unsafe {
+        Bound::from_owned_ptr_or_err(
+            string.py(),
+            ffi::PyUnicode_AsEncodedString(
+                string.as_ptr(),
+                c"utf-8".as_ptr(),
+                c"surrogatepass".as_ptr(),
+            ),
+        )?
+        .cast_into_unchecked::<PyBytes>()
+    };
+    Ok(encoded.as_bytes().to_vec().into())
+}
+
+/// Inverse of [`utf8`]: the `str` whose `surrogatepass` UTF-8 encoding is
+/// `bytes`.
+fn str_from_utf8<'py>(py: Python<'py>, bytes: &[u8]) -> PyResult<Bound<'py, PyString>> {
+    // SAFETY: `bytes` is valid for its length; the result is an owned `str`.
+    unsafe {
+        Ok(Bound::from_owned_ptr_or_err(
+            py,
+            ffi::PyUnicode_DecodeUTF8(
+                bytes.as_ptr().cast(),
+                bytes.len() as ffi::Py_ssize_t,
+                c"surrogatepass".as_ptr(),
+            ),
+        )?
+        .cast_into_unchecked())
     }
 }
 
@@ -218,6 +436,316 @@ unsafe fn ma_keys_of(node: *mut PyObject) -> Option<*const u8> {
     }
 }
 
+/// `Hasher` for object-pointer keys. Addresses are already unique, so
+/// SipHash buys nothing; drop the allocator-alignment zeros and spread
+/// the rest with a Fibonacci multiply so the table's high control bits
+/// stay well distributed.
+#[derive(Default)]
+struct PtrHasher(u64);
+
+impl Hasher for PtrHasher {
+    fn write(&mut self, _bytes: &[u8]) {
+        unreachable!("PtrHasher only hashes pointers");
+    }
+
+    #[inline(always)]
+    fn write_usize(&mut self, addr: usize) {
+        self.0 = ((addr as u64) >> 4).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    }
+
+    #[inline(always)]
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Identity set of Python objects, keyed by address.
+type PtrSet = HashSet<*mut PyObject, BuildHasherDefault<PtrHasher>>;
+
+/// Per-call knobs shared by every walk entry point. All default to the
+/// `ast.walk`-equivalent behaviour.
+#[derive(Clone, Copy)]
+struct WalkOptions {
+    /// Also descend into `tuple` values and `list`/`tuple` subclasses
+    /// found in `_fields`. `ast.iter_child_nodes` only looks inside
+    /// lists, so this is opt-in for trees built by transformers that
+    /// store children in tuples.
+    sequences: bool,
+    /// Route the walk through [`walk_node_dfs_checked`], which raises
+    /// `ValueError` on a cycle through `_fields` instead of looping
+    /// until memory runs out.
+    detect_cycles: bool,
+    /// Raise [`TreeTooLarge`] once more than this many nodes have been
+    /// visited. `usize::MAX` when unlimited, so the per-node check is a
+    /// plain compare with no `Option` unwrapping.
+    max_nodes: usize,
+    /// Expected number of nodes, used to presize the result buffer.
+    /// `None` falls back to the size of the previous walk on this thread
+    /// (see [`Scratch::presize`]).
+    size_hint: Option<usize>,
+    /// How many stack entries ahead `walk_node_unordered` prefetches
+    /// (the pipeline distance), in `1..=MAX_BATCH`. Exposed to Python as
+    /// `batch`.
+    batch: usize,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        Self {
+            sequences: false,
+            detect_cycles: false,
+            max_nodes: usize::MAX,
+            size_hint: None,
+            batch: DEFAULT_BATCH.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Largest accepted prefetch distance. Far beyond any useful depth: the
+/// L1 miss queue holds 10–20 outstanding lines on current cores, and the
+/// header stage already runs twice the distance ahead.
+const MAX_BATCH: usize = 32;
+
+/// Process-wide prefetch distance used when a walk doesn't pass `batch=`.
+/// 4 is tuned on x86_64 desktop parts; [`calibrate`] replaces it with
+/// the best size measured on the running machine.
+static DEFAULT_BATCH: AtomicUsize = AtomicUsize::new(4);
+
+/// Validate a user-supplied prefetch distance.
+fn check_batch(batch: usize) -> PyResult<usize> {
+    if !(1..=MAX_BATCH).contains(&batch) {
+        return Err(PyValueError::new_err(format!(
+            "batch must be between 1 and {MAX_BATCH}, got {batch}"
+        )));
+    }
+    Ok(batch)
+}
+
+create_exception!(
+    fast_walk,
+    TreeTooLarge,
+    PyValueError,
+    "Raised when a walk visits more nodes than its `max_nodes` budget."
+);
+
+/// Build a [`TreeTooLarge`] carrying the budget and the node count at
+/// which the walk gave up as `limit` / `count` attributes.
+#[cold]
+#[inline(never)]
+fn tree_too_large(py: Python<'_>, limit: usize, count: usize) -> PyErr {
+    let err = TreeTooLarge::new_err(format!(
+        "AST walk exceeded max_nodes={limit} (aborted after {count} nodes)"
+    ));
+    let value = err.value(py);
+    if let Err(e) = value
+        .setattr("limit", limit)
+        .and_then(|()| value.setattr("count", count))
+    {
+        return e;
+    }
+    err
+}
+
+/// Per-call traversal state threaded through the walk loops: the
+/// resolved base types, the caller's [`WalkOptions`], the per-thread
+/// `FieldTable`, and owned references to nodes whose pointers the walk
+/// must not merely borrow (see [`own_borrowed`](Self::own_borrowed)),
+/// so those stay valid until the result list is built.
+struct Walker<'t, 'py> {
+    py: Python<'py>,
+    base_ast_and_expr_type: (*mut PyTypeObject, *mut PyTypeObject),
+    py_list_type: *mut PyTypeObject,
+    options: WalkOptions,
+    field_table: &'t mut FieldTable,
+    keepalive: Vec<Bound<'py, PyAny>>,
+    /// Set once Python code may have run mid-walk; from then on every
+    /// node pushed is kept alive in `keepalive`.
+    owns_nodes: bool,
+}
+
+impl<'py> Walker<'_, 'py> {
+    /// Keep each of `nodes` alive for the rest of the call.
+    fn pin(&mut self, nodes: impl IntoIterator<Item = *mut PyObject>) {
+        let py = self.py;
+        // SAFETY: callers only pass nodes the walk currently borrows,
+        // which are alive until Python code runs.
+        self.keepalive.extend(
+            nodes
+                .into_iter()
+                .map(|node| unsafe { Bound::from_borrowed_ptr(py, node) }),
+        );
+    }
+
+    /// Called before the walk runs Python code: classifying a new type,
+    /// or reading a dynamic `_fields` node's children with `getattr`.
+    /// The walk loops hold nodes as pointers borrowed out of instance
+    /// dicts and lists, which that code may change (a `_fields` property
+    /// that clears a list the walk has yet to reach), freeing nodes
+    /// still on the stack or already in the result. So the first time,
+    /// take a reference to `node`, to everything on `stack`, and to
+    /// whatever the loop itself borrows (`others`); [`process_node`]
+    /// then pins every child it pushes for the rest of the call.
+    #[cold]
+    #[inline(never)]
+    fn own_borrowed(
+        &mut self,
+        node: *mut PyObject,
+        stack: &[*mut PyObject],
+        others: impl FnOnce(&mut Self),
+    ) {
+        if self.owns_nodes {
+            return;
+        }
+        self.owns_nodes = true;
+        self.pin(std::iter::once(node).chain(stack.iter().copied()));
+        others(self);
+    }
+
+    /// Enforce [`WalkOptions::max_nodes`] after a node has been emitted.
+    #[inline(always)]
+    fn check_budget(&self, visited: usize) -> PyResult<()> {
+        if visited > self.options.max_nodes {
+            return Err(tree_too_large(self.py, self.options.max_nodes, visited));
+        }
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn is_ast(&self, obj: &Bound<'py, PyAny>) -> bool {
+        issubclass_of_ast(obj.get_type_ptr(), self.base_ast_and_expr_type)
+    }
+
+    /// Whether `obj` can seed a walk. `issubclass_of_ast` only answers
+    /// for strict subclasses; a bare `ast.AST()` instance is a valid
+    /// (childless) root too.
+    fn is_root(&self, obj: &Bound<'py, PyAny>) -> bool {
+        obj.get_type_ptr() == self.base_ast_and_expr_type.0 || self.is_ast(obj)
+    }
+
+    /// Validate item `i` of a multi-root argument: `TypeError` if it is
+    /// not an AST node and `strict` is on.
+    fn check_root_item(
+        &self,
+        item: &Bound<'py, PyAny>,
+        i: usize,
+        strict: bool,
+        func: &str,
+    ) -> PyResult<()> {
+        if strict && !self.is_root(item) {
+            return Err(PyTypeError::new_err(format!(
+                "{func}() expects ast.AST nodes, but item {i} is {}",
+                type_name(item),
+            )));
+        }
+        Ok(())
+    }
+
+    /// Resolve a walk's `node` argument into the roots that seed the
+    /// stack: an AST node on its own, or each item of a list, in order.
+    /// With `strict`, anything else raises `TypeError`; without it,
+    /// non-AST values come back as lone leaves (the pre-validation
+    /// behaviour). Replaces the contents of `roots`.
+    fn roots(
+        &self,
+        node: &Bound<'py, PyAny>,
+        strict: bool,
+        func: &str,
+        roots: &mut Vec<*mut PyObject>,
+    ) -> PyResult<()> {
+        roots.clear();
+        if let Ok(list) = node.cast::<PyList>() {
+            for (i, item) in list.iter().enumerate() {
+                self.check_root_item(&item, i, strict, func)?;
+                // The list keeps the item alive until the walk runs
+                // Python code; the walk pins it from then on.
+                roots.push(item.as_ptr());
+            }
+            return Ok(());
+        }
+        if strict && !self.is_root(node) {
+            return Err(PyTypeError::new_err(format!(
+                "{func}() expects an ast.AST node or a list of them, got {}",
+                type_name(node),
+            )));
+        }
+        roots.push(node.as_ptr());
+        Ok(())
+    }
+
+    /// Slow path for [`FIELDS_DYNAMIC`] types: read `_fields` off the
+    /// instance and fetch each child with `getattr`, exactly like
+    /// `ast.iter_child_nodes` — missing attributes are skipped and any
+    /// `list` (subclasses included) is searched for AST items.
+    #[cold]
+    #[inline(never)]
+    fn push_dynamic_children(
+        &mut self,
+        node: *mut PyObject,
+        stack: &mut Vec<*mut PyObject>,
+    ) -> PyResult<()> {
+        let py = self.py;
+        // SAFETY: `node` is on the walk stack, so it is alive for the call.
+        let node = unsafe { Bound::from_borrowed_ptr(py, node) };
+        let first_child = self.keepalive.len();
+        for name in node.getattr(intern!(py, "_fields"))?.try_iter()? {
+            let value = match node.getattr(name?.cast_into::<PyString>()?) {
+                Ok(value) => value,
+                Err(err) if err.is_instance_of::<PyAttributeError>(py) => continue,
+                Err(err) => return Err(err),
+            };
+            if let Ok(list) = value.cast::<PyList>() {
+                for item in list.iter() {
+                    if self.is_ast(&item) {
+                        self.keepalive.push(item);
+                    }
+                }
+            } else if self.options.sequences
+                && let Ok(tuple) = value.cast::<PyTuple>()
+            {
+                for item in tuple.iter() {
+                    if self.is_ast(&item) {
+                        self.keepalive.push(item);
+                    }
+                }
+            } else if self.is_ast(&value) {
+                self.keepalive.push(value);
+            }
+        }
+        // Reverse so children pop in field order, like the fast path.
+        stack.extend(
+            self.keepalive[first_child..]
+                .iter()
+                .rev()
+                .map(Bound::as_ptr),
+        );
+        Ok(())
+    }
+
+    /// Push the AST items of a non-`list` sequence value when
+    /// [`WalkOptions::sequences`] is on: tuples (read straight from
+    /// `ob_item`, like the list fast path) and `list` subclasses.
+    /// Anything else — `str`, `bytes`, `None`, numbers — is ignored.
+    #[inline]
+    unsafe fn push_sequence_items(&self, value: *mut PyObject, stack: &mut Vec<*mut PyObject>) {
+        let (ob_item, length) = unsafe {
+            if ffi::PyTuple_Check(value) != 0 {
+                let tuple = value as *mut ffi::PyTupleObject;
+                ((*tuple).ob_item.as_mut_ptr(), ffi::Py_SIZE(value))
+            } else if ffi::PyList_Check(value) != 0 {
+                ((*(value as *mut PyListObject)).ob_item, ffi::Py_SIZE(value))
+            } else {
+                return;
+            }
+        };
+        for i in (0..length).rev() {
+            let child = unsafe { *ob_item.offset(i) };
+            if issubclass_of_ast(unsafe { ffi::Py_TYPE(child) }, self.base_ast_and_expr_type) {
+                stack.push(child);
+            }
+        }
+    }
+}
+
 /// Per-node body shared by both traversals: enumerate the `_fields`
 /// slots of the node's instance dict and push AST children onto
 /// `stack`. For parsed ASTs, CPython stores dict keys in the order
@@ -231,29 +759,62 @@ unsafe fn ma_keys_of(node: *mut PyObject) -> Option<*const u8> {
 /// already catches every primitive (str/int/None/...) in one load, and
 /// a table probe costs the same on average — substituting one for the
 /// other was measured to regress.
+///
+/// `borrowed` takes a reference to the nodes the calling loop holds
+/// outside `stack` (its result, its own stack), for
+/// [`Walker::own_borrowed`].
 #[inline(always)]
-unsafe fn process_node(
+unsafe fn process_node<'py>(
+    walker: &mut Walker<'_, 'py>,
     current_node: *mut PyObject,
-    base_ast_and_expr_type: (*mut PyTypeObject, *mut PyTypeObject),
-    py_list_type: *mut PyTypeObject,
-    field_table: &FieldTable,
     stack: &mut Vec<*mut PyObject>,
-) {
+    borrowed: impl FnOnce(&mut Walker<'_, 'py>),
+) -> PyResult<()> {
+    let first_child = stack.len();
+    unsafe { push_children(walker, current_node, stack, borrowed)? };
+    if walker.owns_nodes {
+        walker.pin(stack[first_child..].iter().copied());
+    }
+    Ok(())
+}
+
+/// [`process_node`] without the pinning of pushed children.
+#[inline(always)]
+unsafe fn push_children<'py>(
+    walker: &mut Walker<'_, 'py>,
+    current_node: *mut PyObject,
+    stack: &mut Vec<*mut PyObject>,
+    borrowed: impl FnOnce(&mut Walker<'_, 'py>),
+) -> PyResult<()> {
+    let base_ast_and_expr_type = walker.base_ast_and_expr_type;
+    let py_list_type = walker.py_list_type;
     let type_ptr = unsafe { ffi::Py_TYPE(current_node) };
-    let encoded = field_table.lookup(type_ptr);
-    // 0 == not an AST type we know about. Shouldn't normally happen
-    // (only AST nodes reach the stack) but guards any caller that
-    // seeds the walk with a non-AST root.
+    let mut encoded = walker.field_table.lookup(type_ptr);
+    if encoded == 0 || encoded == FIELDS_DYNAMIC {
+        walker.own_borrowed(current_node, stack, borrowed);
+        if encoded == 0 {
+            encoded =
+                walker
+                    .field_table
+                    .insert_late_type(walker.py, type_ptr, base_ast_and_expr_type.0);
+        }
+    }
+    // 0 == not an AST type. Shouldn't normally happen (only AST nodes
+    // reach the stack) but guards any caller that seeds the walk with a
+    // non-AST root.
     if encoded == 0 {
-        return;
+        return Ok(());
+    }
+    if encoded == FIELDS_DYNAMIC {
+        return walker.push_dynamic_children(current_node, stack);
     }
     let n_fields = (encoded - 1) as usize;
     if n_fields == 0 {
-        return;
+        return Ok(());
     }
 
     let Some(dict) = get_instance_dict_fast(current_node) else {
-        return;
+        return Ok(());
     };
 
     for item_ptr in
@@ -273,19 +834,26 @@ unsafe fn process_node(
             }
         } else if issubclass_of_ast(item_type, base_ast_and_expr_type) {
             stack.push(item_ptr);
+        } else if walker.options.sequences {
+            unsafe { walker.push_sequence_items(item_ptr, stack) };
         }
     }
+    Ok(())
 }
 
-/// Check whether `subtype` is a subclass of `ast.AST` within the first two
-/// levels of the MRO. Every stdlib AST node is `Concrete -> ast.expr/stmt
-/// -> ast.AST` or `Concrete -> ast.AST`, so two hops suffice.
+/// Check whether `subtype` is a subclass of `ast.AST`. Every stdlib AST
+/// node is `Concrete -> ast.expr/stmt -> ast.AST` or `Concrete ->
+/// ast.AST`, so the first two levels of the MRO are checked inline; only
+/// deeper user subclasses (`class MyName(ast.Name)` and below) fall
+/// through to `PyType_IsSubtype`.
 ///
 /// Performance notes baked in here:
 /// - Early-exit on `first_supertype == PyBaseObject_Type`: primitives like
 ///   `str`, `NoneType`, `float`, `bytes` inherit directly from `object`,
 ///   AST subclasses never do. This skips the scattered second `tp_base`
 ///   load on ~40% of items (non-AST values in `_fields` slots).
+/// - Two-level non-AST types (`bool -> int -> object`) stop at the second
+///   hop too, so the full MRO scan is reserved for genuinely deep types.
 fn issubclass_of_ast(
     subtype: *mut PyTypeObject,
     base_ast_and_expr_type: (*mut PyTypeObject, *mut PyTypeObject),
@@ -303,78 +871,229 @@ fn issubclass_of_ast(
         return true;
     }
     let second_supertype = unsafe { (*first_supertype).tp_base };
-    second_supertype == base_ast_type || second_supertype == base_expr_type
+    if second_supertype == base_ast_type || second_supertype == base_expr_type {
+        return true;
+    }
+    if second_supertype.is_null() || second_supertype == py_object_type {
+        return false;
+    }
+    unsafe { ffi::PyType_IsSubtype(subtype, base_ast_type) != 0 }
 }
 
-/// Strict depth-first pre-order traversal.
+/// Strict depth-first pre-order traversal. Multiple roots are walked
+/// one after another, in order.
 fn walk_node_dfs(
-    node: *mut PyObject,
-    base_ast_and_expr_type: (*mut PyTypeObject, *mut PyTypeObject),
-    py_list_type: *mut PyTypeObject,
-    field_table: &FieldTable,
+    walker: &mut Walker<'_, '_>,
+    roots: &[*mut PyObject],
+    stack: &mut Vec<*mut PyObject>,
     result_list: &mut Vec<*mut PyObject>,
 ) -> PyResult<()> {
-    let mut stack = vec![node];
+    stack.clear();
+    stack.extend(roots.iter().rev().copied());
 
     while let Some(current_node) = stack.pop() {
         result_list.push(current_node);
+        walker.check_budget(result_list.len())?;
         unsafe {
-            process_node(
-                current_node,
-                base_ast_and_expr_type,
-                py_list_type,
-                field_table,
-                &mut stack,
-            );
+            process_node(walker, current_node, stack, |w| {
+                w.pin(result_list.iter().copied())
+            })?
+        };
+    }
+
+    Ok(())
+}
+
+/// Run the traversal behind `walk_dfs` (`dfs`) or `walk_unordered`
+/// over `roots`, switching to the checked loop when cycle detection is
+/// on. Nodes are appended to `result_list`; `stack` is scratch space.
+fn walk_roots(
+    walker: &mut Walker<'_, '_>,
+    roots: &[*mut PyObject],
+    dfs: bool,
+    stack: &mut Vec<*mut PyObject>,
+    result_list: &mut Vec<*mut PyObject>,
+) -> PyResult<()> {
+    if walker.options.detect_cycles {
+        walk_node_dfs_checked(walker, roots, result_list)
+    } else if dfs {
+        walk_node_dfs(walker, roots, stack, result_list)
+    } else {
+        walk_node_unordered(walker, roots, stack, result_list)
+    }
+}
+
+/// Depth-first pre-order traversal that refuses to loop on malformed
+/// trees. Tracks the current root-to-node path; a node that turns up
+/// while it is already on that path is its own ancestor, i.e. some
+/// transformer put an ancestor back into `_fields`. Nodes that merely
+/// appear under several parents (`ast.Load()` and friends, shared
+/// subtrees) are not cycles and are visited as usual.
+///
+/// Produces exactly the sequence of `walk_node_dfs` on acyclic input,
+/// at the cost of a hash-set probe per node.
+fn walk_node_dfs_checked(
+    walker: &mut Walker<'_, '_>,
+    roots: &[*mut PyObject],
+    result_list: &mut Vec<*mut PyObject>,
+) -> PyResult<()> {
+    let mut stack: Vec<_> = roots.iter().rev().map(|&root| (root, 0usize)).collect();
+    let mut path: Vec<*mut PyObject> = Vec::new();
+    let mut on_path = PtrSet::default();
+    let mut children = Vec::new();
+
+    while let Some((current_node, depth)) = stack.pop() {
+        for finished in path.drain(depth..) {
+            on_path.remove(&finished);
         }
+        if !on_path.insert(current_node) {
+            // Only roots have depth 0, and the path is empty when one is
+            // popped, so `depth - 1` is the parent.
+            return Err(cycle_error(walker.py, path[depth - 1], current_node));
+        }
+        path.push(current_node);
+        result_list.push(current_node);
+        walker.check_budget(result_list.len())?;
+        unsafe {
+            process_node(walker, current_node, &mut children, |w| {
+                w.pin(result_list.iter().copied());
+                w.pin(stack.iter().map(|&(node, _)| node));
+            })?
+        };
+        stack.extend(children.drain(..).map(|child| (child, depth + 1)));
     }
 
     Ok(())
 }
 
-/// Batched traversal with prefetching.
+/// Depth-first pre-order traversal that emits each distinct node object
+/// once, at its first occurrence. Shared singletons (`ast.Load()`) and
+/// subtrees shared between parents (optimizer DAGs) are neither
+/// repeated in the result nor re-expanded, and a node that has already
+/// been seen is never expanded again — so this also terminates on
+/// cyclic input.
 ///
-/// Drains up to `BATCH` nodes from the stack, issues an L1 prefetch for
-/// each node's `PyDictKeysObject` in a tight loop, then processes each
-/// node in turn. Prefetches issued in parallel hide the latency of the
-/// scattered dict-keys loads that dominate the DFS profile (~20% of
-/// function time). Visits the same set of nodes as `walk_node_dfs` but
-/// not in strict DFS order.
-fn walk_node_unordered(
-    node: *mut PyObject,
-    base_ast_and_expr_type: (*mut PyTypeObject, *mut PyTypeObject),
-    py_list_type: *mut PyTypeObject,
-    field_table: &FieldTable,
+/// On acyclic trees the result is `walk_node_dfs`'s sequence with later
+/// duplicates dropped: by the time a node is reached a second time its
+/// whole subtree has already been emitted.
+fn walk_node_unique(
+    walker: &mut Walker<'_, '_>,
+    roots: &[*mut PyObject],
+    stack: &mut Vec<*mut PyObject>,
     result_list: &mut Vec<*mut PyObject>,
 ) -> PyResult<()> {
-    const BATCH: usize = 4;
-    let mut stack = vec![node];
-    let mut batch: [*mut PyObject; BATCH] = [std::ptr::null_mut(); BATCH];
+    stack.clear();
+    stack.extend(roots.iter().rev().copied());
+    let mut seen = PtrSet::default();
 
-    while !stack.is_empty() {
-        let take = stack.len().min(BATCH);
-        for slot in batch.iter_mut().take(take) {
-            *slot = stack.pop().unwrap();
+    while let Some(current_node) = stack.pop() {
+        if !seen.insert(current_node) {
+            continue;
         }
+        result_list.push(current_node);
+        walker.check_budget(result_list.len())?;
+        unsafe {
+            process_node(walker, current_node, stack, |w| {
+                w.pin(result_list.iter().copied())
+            })?
+        };
+    }
 
-        for &node_ptr in batch.iter().take(take) {
-            if let Some(p) = unsafe { ma_keys_of(node_ptr) } {
-                unsafe { prefetch_l1(p) };
+    Ok(())
+}
+
+/// Build the `ValueError` for a cycle found by [`walk_node_dfs_checked`]:
+/// `child` is already an ancestor of `parent`. Names the node type and
+/// the `_fields` slot that closes the loop, e.g. `List.elts[1]`.
+#[cold]
+#[inline(never)]
+fn cycle_error(py: Python<'_>, parent: *mut PyObject, child: *mut PyObject) -> PyErr {
+    // SAFETY: both nodes are on the walk path, so they are alive.
+    let parent = unsafe { Bound::from_borrowed_ptr(py, parent) };
+    let child = unsafe { Bound::from_borrowed_ptr(py, child) };
+    let parent_type = type_name(&parent);
+    let slot = field_slot_of(&parent, &child).unwrap_or_default();
+    PyValueError::new_err(format!(
+        "AST contains a cycle: {} node is reachable from itself through {}{}",
+        type_name(&child),
+        parent_type,
+        slot,
+    ))
+}
+
+/// `type(obj).__name__`, for error messages.
+fn type_name(obj: &Bound<'_, PyAny>) -> String {
+    obj.get_type()
+        .name()
+        .map_or_else(|_| "?".to_owned(), |n| n.to_string())
+}
+
+/// Locate `child` among `parent`'s `_fields`, returning `.field` or
+/// `.field[index]`. Error path only, so plain attribute access is fine.
+fn field_slot_of(parent: &Bound<'_, PyAny>, child: &Bound<'_, PyAny>) -> Option<String> {
+    for name in parent.getattr("_fields").ok()?.try_iter().ok()? {
+        let name = name.ok()?.cast_into::<PyString>().ok()?;
+        let Ok(value) = parent.getattr(&name) else {
+            continue;
+        };
+        if value.is(child) {
+            return Some(format!(".{name}"));
+        }
+        if value.is_instance_of::<PyList>() || value.is_instance_of::<PyTuple>() {
+            for (i, item) in value.try_iter().ok()?.enumerate() {
+                if item.ok()?.is(child) {
+                    return Some(format!(".{name}[{i}]"));
+                }
             }
         }
+    }
+    None
+}
 
-        for &current in batch.iter().take(take) {
-            result_list.push(current);
-            unsafe {
-                process_node(
-                    current,
-                    base_ast_and_expr_type,
-                    py_list_type,
-                    field_table,
-                    &mut stack,
-                );
+/// Software-pipelined traversal with prefetching.
+///
+/// Pops one entry per iteration, like `walk_node_dfs`, while two
+/// prefetch stages run ahead on entries further down the stack, `d =`
+/// [`WalkOptions::batch`] apart: the object `2d` entries down gets its
+/// header (type pointer and dict slot) prefetched, and the one `d`
+/// entries down — whose header has arrived by then — gets its
+/// `PyDictKeysObject` prefetched. By the time a node is popped, both of
+/// the scattered loads `process_node` starts with are in flight or
+/// done. Children pushed in the meantime only delay a node, so a
+/// prefetch is early rather than wasted.
+///
+/// Visits the same nodes as `walk_node_dfs`, currently in the same
+/// order, though callers must not rely on that.
+fn walk_node_unordered(
+    walker: &mut Walker<'_, '_>,
+    roots: &[*mut PyObject],
+    stack: &mut Vec<*mut PyObject>,
+    result_list: &mut Vec<*mut PyObject>,
+) -> PyResult<()> {
+    let distance = walker.options.batch;
+    stack.clear();
+    stack.extend(roots.iter().rev().copied());
+
+    while let Some(current) = stack.pop() {
+        let len = stack.len();
+        if len >= 2 * distance {
+            let ahead = unsafe { *stack.get_unchecked(len - 2 * distance) };
+            unsafe { prefetch_l1(ahead as *const u8) };
+        }
+        if len >= distance {
+            let ahead = unsafe { *stack.get_unchecked(len - distance) };
+            if let Some(p) = unsafe { ma_keys_of(ahead) } {
+                unsafe { prefetch_l1(p) };
             }
         }
+
+        result_list.push(current);
+        walker.check_budget(result_list.len())?;
+        unsafe {
+            process_node(walker, current, stack, |w| {
+                w.pin(result_list.iter().copied())
+            })?
+        };
     }
 
     Ok(())
@@ -385,6 +1104,106 @@ thread_local! {
         const { Cell::new(None) };
     static AST_FIELD_TABLE: RefCell<Option<Box<FieldTable>>> =
         const { RefCell::new(None) };
+    static WALK_SCRATCH: RefCell<Scratch> = const { RefCell::new(Scratch::new()) };
+}
+
+/// Largest buffer (in pointers) [`Scratch`] keeps between walks. One
+/// pathological tree shouldn't pin its peak footprint on the thread for
+/// good; anything bigger is freed and regrown on the next large walk.
+const SCRATCH_RETAIN_LIMIT: usize = 1 << 20;
+
+/// Per-thread buffers reused across walks, so that once they have grown
+/// to the working-set size a walk does no Rust heap allocation at all:
+/// the resolved roots, the traversal stack, the visited nodes (borrowed
+/// pointers, valid until the call returns), the references an `into=`
+/// refill displaces, and `walk_grouped`'s per-type buckets. `last_len`
+/// remembers how many nodes the previous walk produced, as the default
+/// size estimate for the next.
+struct Scratch {
+    roots: Vec<*mut PyObject>,
+    stack: Vec<*mut PyObject>,
+    result: Vec<*mut PyObject>,
+    spare: Vec<*mut PyObject>,
+    buckets: Vec<(usize, *mut PyObject)>,
+    last_len: usize,
+}
+
+impl Scratch {
+    const fn new() -> Self {
+        Self {
+            roots: Vec::new(),
+            stack: Vec::new(),
+            result: Vec::new(),
+            spare: Vec::new(),
+            buckets: Vec::new(),
+            last_len: 0,
+        }
+    }
+
+    /// Reserve room for the expected result up front, so a large walk
+    /// grows `result` once rather than by repeated doubling. Trees
+    /// walked back to back on one thread tend to be of similar size, so
+    /// without a hint the previous walk's size is the estimate. The
+    /// estimate never exceeds the node budget, and a reservation that
+    /// fails is simply skipped: it is only a hint.
+    fn presize(&mut self, options: &WalkOptions) {
+        let expected = options
+            .size_hint
+            .unwrap_or(self.last_len)
+            .min(options.max_nodes.saturating_add(1));
+        let _ = self.result.try_reserve(expected);
+    }
+
+    /// Drop the borrowed pointers and any oversized allocation, keeping
+    /// the size estimate.
+    fn reset(&mut self) {
+        self.last_len = self.result.len();
+        for buf in [
+            &mut self.roots,
+            &mut self.stack,
+            &mut self.result,
+            &mut self.spare,
+        ] {
+            if buf.capacity() > SCRATCH_RETAIN_LIMIT {
+                *buf = Vec::new();
+            } else {
+                buf.clear();
+            }
+        }
+        self.buckets.clear();
+    }
+
+    /// Turn the collected `result` into the Python return value: a new
+    /// list, or `into` refilled in place.
+    fn finish<'py>(
+        &mut self,
+        py: Python<'py>,
+        into: Option<Bound<'py, PyList>>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        match into {
+            None => vec_into_pylist(py, &self.result),
+            Some(list) => {
+                refill_pylist(&list, &self.result, &mut self.spare)?;
+                Ok(list.into_any())
+            }
+        }
+    }
+}
+
+/// Run `body` with this thread's [`Scratch`], cleared afterwards. A walk
+/// re-entered from Python code running mid-walk (a dynamic `_fields`
+/// property, or a `__del__` — say of an entry `into=` displaces) finds
+/// the buffers taken and gets fresh ones, as it gets a private field
+/// table from [`with_field_table`].
+fn with_scratch<R>(body: impl FnOnce(&mut Scratch) -> R) -> R {
+    WALK_SCRATCH.with(|cell| match cell.try_borrow_mut() {
+        Ok(mut scratch) => {
+            let result = body(&mut scratch);
+            scratch.reset();
+            result
+        }
+        Err(_) => body(&mut Scratch::new()),
+    })
 }
 
 /// Resolve `ast.AST` and `ast.expr` to their raw type pointers. Kept out
@@ -409,52 +1228,124 @@ fn resolve_base_types(py: Python) -> PyResult<(*mut PyTypeObject, *mut PyTypeObj
     })
 }
 
+/// `FieldTable` value for an AST type: [`FIELDS_DYNAMIC`] if the class
+/// opts into per-instance `_fields`, otherwise `len(_fields) + 1`.
+fn type_encoding(t: &Bound<'_, PyType>) -> u8 {
+    let dynamic = t
+        .getattr("_fast_walk_dynamic_fields")
+        .and_then(|flag| flag.is_truthy())
+        .unwrap_or(false);
+    if dynamic {
+        return FIELDS_DYNAMIC;
+    }
+    let n_fields = t
+        .getattr("_fields")
+        .ok()
+        .and_then(|f| f.len().ok())
+        .unwrap_or(0);
+    // `_fields` tuples in the stdlib top out at 7 entries. Saturate
+    // for safety so a rogue subclass with a huge `_fields` tuple
+    // can't break the u8 encoding.
+    // Saturate to u8::MAX - 2: the table stores n_fields+1 as the
+    // encoded value and reserves 255 for `FIELDS_DYNAMIC`.
+    n_fields.min((u8::MAX - 2) as usize) as u8 + 1
+}
+
 /// Walk every subclass of `ast.AST` at first-use and record each type's
 /// `len(_fields)`. The resulting direct-mapped table answers the hot-loop
 /// lookup in one L1 load per node — no Python calls, no `_attributes`
-/// scanning, no hashing.
+/// scanning, no hashing. Subclasses created after this snapshot are
+/// picked up lazily by [`FieldTable::insert_late_type`].
+///
+/// The stdlib classes met along the way, in visiting order, become the
+/// table's type-code vocabulary. Registration order of
+/// `__subclasses__()` is fixed when `_ast` initializes, and user
+/// subclasses are skipped, so the vocabulary is the same in every
+/// process running the same Python version.
 #[inline(never)]
 fn prebuild_field_table(py: Python<'_>) -> PyResult<Box<FieldTable>> {
     let ast_module = py.import("ast")?;
     let ast_class = ast_module.getattr("AST")?.cast_into::<PyType>()?;
 
-    let mut table = Box::new(FieldTable::new());
+    let mut order = Vec::new();
     let mut stack: Vec<Bound<'_, PyType>> = vec![ast_class];
     while let Some(t) = stack.pop() {
-        let n_fields = t
-            .getattr("_fields")
-            .ok()
-            .and_then(|f| f.len().ok())
-            .unwrap_or(0);
-        // `_fields` tuples in the stdlib top out at 7 entries. Saturate
-        // for safety so a rogue subclass with a huge `_fields` tuple
-        // can't break the u8 encoding.
-        // Saturate to u8::MAX - 1: the table reserves n_fields+1 as the
-        // encoded value so we need headroom below 255.
-        let n = n_fields.min((u8::MAX - 1) as usize) as u8;
-        table.insert_ast(t.as_type_ptr(), n);
         let subs = t.call_method0("__subclasses__")?;
         for sub in subs.try_iter()? {
             stack.push(sub?.cast_into::<PyType>()?);
         }
+        order.push(t);
     }
+
+    let mut table = Box::new(FieldTable::new());
+    for t in &order {
+        let module = t.getattr(intern!(py, "__module__"))?;
+        let stdlib = module.eq("ast")? || module.eq("_ast")?;
+        if stdlib && !table.vocabulary.iter().any(|v| v.is(t)) {
+            table.vocabulary.push(t.clone().unbind());
+        }
+    }
+    // Parents come before their subclasses, so each non-stdlib type
+    // finds its nearest stdlib ancestor already inserted.
+    for t in &order {
+        table.insert_type(t);
+    }
+    table.prebuilt = table.pinned.len();
     Ok(table)
 }
 
-/// Run `body` with a `&FieldTable` pinning the prebuilt `_fields`-length
-/// cache. The table is built on first use per thread and reused for all
-/// subsequent walks on that thread.
+/// Run `body` with a `&mut FieldTable` pinning the prebuilt
+/// `_fields`-length cache. The table is built on first use per thread
+/// and reused (and lazily extended) by all subsequent walks on that
+/// thread; once it has picked up more than [`MAX_LATE_TYPES`] types
+/// since, the next call first drops them. A walk re-entered from
+/// Python code running mid-walk (a `__del__`, a dynamic `_fields`
+/// property) finds the table taken and builds a private one for the
+/// call, like [`with_scratch`].
 #[inline(always)]
 fn with_field_table<R>(
     py: Python<'_>,
-    body: impl FnOnce(&FieldTable) -> PyResult<R>,
+    body: impl FnOnce(&mut FieldTable) -> PyResult<R>,
 ) -> PyResult<R> {
-    AST_FIELD_TABLE.with(|cache| {
-        let mut borrow = cache.borrow_mut();
-        if borrow.is_none() {
-            *borrow = Some(prebuild_field_table(py)?);
+    AST_FIELD_TABLE.with(|cache| match cache.try_borrow_mut() {
+        Ok(mut borrow) => {
+            let table = match &mut *borrow {
+                Some(table) => table,
+                None => borrow.insert(prebuild_field_table(py)?),
+            };
+            if table.pinned.len() - table.prebuilt > MAX_LATE_TYPES {
+                table.forget_late_types();
+            }
+            body(table)
         }
-        body(borrow.as_ref().unwrap())
+        Err(_) => body(&mut *prebuild_field_table(py)?),
+    })
+}
+
+/// Run `body` with a [`Walker`] over this thread's `FieldTable`, plus
+/// this thread's [`Scratch`] buffers.
+#[inline(always)]
+fn with_walker<'py, R>(
+    py: Python<'py>,
+    options: WalkOptions,
+    body: impl FnOnce(&mut Walker<'_, 'py>, &mut Scratch) -> PyResult<R>,
+) -> PyResult<R> {
+    let base_ast_and_expr_type = resolve_base_types(py)?;
+    let py_list_type = PyList::type_object_raw(py);
+    with_field_table(py, |field_table| {
+        let mut walker = Walker {
+            py,
+            base_ast_and_expr_type,
+            py_list_type,
+            options,
+            field_table,
+            keepalive: Vec::new(),
+            owns_nodes: false,
+        };
+        with_scratch(|scratch| {
+            scratch.presize(&options);
+            body(&mut walker, scratch)
+        })
     })
 }
 
@@ -477,21 +1368,185 @@ fn vec_into_pylist<'py>(py: Python<'py>, items: &[*mut PyObject]) -> PyResult<Bo
     }
 }
 
+/// Replace the contents of `list` with `items` (borrowed pointers). If
+/// the list's allocation already fits, the items are written straight
+/// into `ob_item` with no temporary list; the displaced references are
+/// parked in `spare` and released only once the list is consistent
+/// again, since dropping them can run arbitrary Python code.
+fn refill_pylist(
+    list: &Bound<'_, PyList>,
+    items: &[*mut PyObject],
+    spare: &mut Vec<*mut PyObject>,
+) -> PyResult<()> {
+    let py = list.py();
+    let list_ptr = list.as_ptr();
+    let len = items.len() as ffi::Py_ssize_t;
+    unsafe {
+        let raw = list_ptr as *mut PyListObject;
+        if (*raw).allocated < len {
+            let fresh = vec_into_pylist(py, items)?;
+            if ffi::PyList_SetSlice(list_ptr, 0, ffi::PY_SSIZE_T_MAX, fresh.as_ptr()) < 0 {
+                return Err(PyErr::fetch(py));
+            }
+            return Ok(());
+        }
+        for &ptr in items {
+            ffi::Py_INCREF(ptr);
+        }
+        let ob_item = (*raw).ob_item;
+        let old_len = (*raw).ob_base.ob_size as usize;
+        spare.clear();
+        if old_len > 0 {
+            spare.extend_from_slice(std::slice::from_raw_parts(ob_item, old_len));
+        }
+        if !items.is_empty() {
+            std::ptr::copy_nonoverlapping(items.as_ptr(), ob_item, items.len());
+        }
+        (*raw).ob_base.ob_size = len;
+        for old in spare.drain(..) {
+            ffi::Py_DECREF(old);
+        }
+    }
+    Ok(())
+}
+
+/// Split `nodes` into one list per node type, as a dict keyed by type in
+/// order of each type's first appearance.
+///
+/// [`FieldTable`] slots double as bucket indices: every AST type the walk
+/// met has one, so a counting pass sizes each list exactly and a second
+/// pass fills it in place — no hashing and no per-append growth. Each
+/// bucket is `(count, list)`; the count becomes the fill cursor once the
+/// lists exist. Values the table doesn't know (non-AST roots of a
+/// `strict=False` walk) go through the dict at the end, after every
+/// list is complete.
+fn group_by_type<'py>(
+    py: Python<'py>,
+    field_table: &FieldTable,
+    nodes: &[*mut PyObject],
+    buckets: &mut Vec<(usize, *mut PyObject)>,
+) -> PyResult<Bound<'py, PyDict>> {
+    buckets.clear();
+    buckets.resize(field_table.keys.len(), (0, std::ptr::null_mut()));
+    let mut order = Vec::new();
+    let mut strays = Vec::new();
+    for &node in nodes {
+        match field_table.slot_for(unsafe { ffi::Py_TYPE(node) }) {
+            Some(slot) => {
+                let count = &mut buckets[slot].0;
+                if *count == 0 {
+                    order.push(slot);
+                }
+                *count += 1;
+            }
+            None => strays.push(node),
+        }
+    }
+
+    let mut lists = Vec::with_capacity(order.len());
+    for &slot in &order {
+        let (count, list) = &mut buckets[slot];
+        unsafe {
+            let list_ptr = ffi::PyList_New(*count as ffi::Py_ssize_t);
+            if list_ptr.is_null() {
+                return Err(PyErr::fetch(py));
+            }
+            lists.push((field_table.keys[slot], Bound::from_owned_ptr(py, list_ptr)));
+            *list = list_ptr;
+        }
+        *count = 0;
+    }
+    for &node in nodes {
+        if let Some(slot) = field_table.slot_for(unsafe { ffi::Py_TYPE(node) }) {
+            let (fill, list) = &mut buckets[slot];
+            unsafe {
+                ffi::Py_INCREF(node);
+                *(*(*list as *mut PyListObject)).ob_item.add(*fill) = node;
+            }
+            *fill += 1;
+        }
+    }
+
+    let groups = PyDict::new(py);
+    for (key, list) in lists {
+        // The table pins every type it holds.
+        let node_type = unsafe { Bound::from_borrowed_ptr(py, key as *mut PyObject) };
+        groups.set_item(node_type, list)?;
+    }
+    for node in strays {
+        let node = unsafe { Bound::from_borrowed_ptr(py, node) };
+        let node_type = node.get_type();
+        match groups.get_item(&node_type)? {
+            Some(list) => list.cast_into::<PyList>()?.append(node)?,
+            None => groups.set_item(node_type, PyList::new(py, [node])?)?,
+        }
+    }
+    Ok(groups)
+}
+
 /// Walk the AST rooted at `node` in strict depth-first pre-order and
 /// return every descendant (including `node` itself) as a list.
 ///
-/// Semantically equivalent to `list(ast.walk(node))` but ~100× faster.
+/// Semantically equivalent to `list(ast.walk(node))` but ~40× faster.
 /// Use `walk_unordered` if traversal order doesn't matter — it's faster
-/// still.
+/// on trees too large for the CPU cache.
+///
+/// `sequences=True` additionally descends into tuple-valued fields and
+/// `list`/`tuple` subclasses, which `ast.walk` ignores.
+///
+/// `detect_cycles=True` raises `ValueError` if some node is reachable
+/// from itself through `_fields`, instead of looping forever.
+///
+/// `max_nodes` caps the walk: once more than that many nodes have been
+/// visited it raises `TreeTooLarge` rather than building a huge list.
+///
+/// `node` may also be a list of AST nodes (e.g. `tree.body`), walked one
+/// root after another. Anything else raises `TypeError`, unless
+/// `strict=False`, in which case a non-AST value is returned as `[node]`.
+///
+/// `into` takes an existing list, which is cleared, refilled with the
+/// result and returned — handy for hot loops that walk many trees.
+///
+/// `size_hint` is the expected node count, used to allocate the result
+/// buffer once up front; without it the previous walk's size on this
+/// thread is used. It only affects allocation, never the result.
+///
+/// A per-thread type table keeps every AST class the walk meets alive;
+/// classes defined after the thread's first walk are released again
+/// once more than 1024 of them have accumulated.
 #[pyfunction]
-fn walk_dfs<'py>(py: Python<'py>, node: Bound<'py, PyAny>) -> PyResult<Bound<'py, PyAny>> {
-    let base = resolve_base_types(py)?;
-    let py_list_type = PyList::type_object_raw(py);
-    let node_ptr = node.as_ptr();
-    with_field_table(py, |table| {
-        let mut result_list = Vec::new();
-        walk_node_dfs(node_ptr, base, py_list_type, table, &mut result_list)?;
-        vec_into_pylist(py, &result_list)
+#[pyo3(signature = (
+    node, *, sequences = false, detect_cycles = false, max_nodes = None, strict = true,
+    into = None, size_hint = None,
+))]
+#[allow(clippy::too_many_arguments)]
+fn walk_dfs<'py>(
+    py: Python<'py>,
+    node: Bound<'py, PyAny>,
+    sequences: bool,
+    detect_cycles: bool,
+    max_nodes: Option<usize>,
+    strict: bool,
+    into: Option<Bound<'py, PyList>>,
+    size_hint: Option<usize>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let options = WalkOptions {
+        sequences,
+        detect_cycles,
+        max_nodes: max_nodes.unwrap_or(usize::MAX),
+        size_hint,
+        ..WalkOptions::default()
+    };
+    with_walker(py, options, |walker, scratch| {
+        walker.roots(&node, strict, "walk_dfs", &mut scratch.roots)?;
+        walk_roots(
+            walker,
+            &scratch.roots,
+            true,
+            &mut scratch.stack,
+            &mut scratch.result,
+        )?;
+        scratch.finish(py, into)
     })
 }
 
@@ -500,24 +1555,604 @@ fn walk_dfs<'py>(py: Python<'py>, node: Bound<'py, PyAny>) -> PyResult<Bound<'py
 ///
 /// The set of returned nodes is identical to `walk_dfs` and to
 /// `ast.walk`; only the order differs. Use this whenever order is not
-/// significant — batched prefetching makes it ~25% faster than
-/// `walk_dfs`.
+/// significant — it prefetches ahead of the traversal to hide the cache
+/// misses `walk_dfs` waits on, which pays off on trees too large for
+/// the cache and costs a little on small ones.
+///
+/// Accepts the same `node` forms and keyword arguments as `walk_dfs`.
+/// Cycle detection needs the ancestor path, so with it enabled the walk
+/// falls back to the checked depth-first loop.
+///
+/// `batch` is how many nodes ahead the walk prefetches (1 to 32).
+/// Defaults to the process-wide value, 4 unless `calibrate()` picked
+/// another.
 #[pyfunction]
-fn walk_unordered<'py>(py: Python<'py>, node: Bound<'py, PyAny>) -> PyResult<Bound<'py, PyAny>> {
-    let base = resolve_base_types(py)?;
-    let py_list_type = PyList::type_object_raw(py);
-    let node_ptr = node.as_ptr();
-    with_field_table(py, |table| {
-        let mut result_list = Vec::new();
-        walk_node_unordered(node_ptr, base, py_list_type, table, &mut result_list)?;
-        vec_into_pylist(py, &result_list)
+#[pyo3(signature = (
+    node, *, sequences = false, detect_cycles = false, max_nodes = None, strict = true,
+    into = None, size_hint = None, batch = None,
+))]
+#[allow(clippy::too_many_arguments)]
+fn walk_unordered<'py>(
+    py: Python<'py>,
+    node: Bound<'py, PyAny>,
+    sequences: bool,
+    detect_cycles: bool,
+    max_nodes: Option<usize>,
+    strict: bool,
+    into: Option<Bound<'py, PyList>>,
+    size_hint: Option<usize>,
+    batch: Option<usize>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let mut options = WalkOptions {
+        sequences,
+        detect_cycles,
+        max_nodes: max_nodes.unwrap_or(usize::MAX),
+        size_hint,
+        ..WalkOptions::default()
+    };
+    if let Some(batch) = batch {
+        options.batch = check_batch(batch)?;
+    }
+    with_walker(py, options, |walker, scratch| {
+        walker.roots(&node, strict, "walk_unordered", &mut scratch.roots)?;
+        walk_roots(
+            walker,
+            &scratch.roots,
+            false,
+            &mut scratch.stack,
+            &mut scratch.result,
+        )?;
+        scratch.finish(py, into)
     })
 }
 
+/// Walk the AST rooted at `node` and return each distinct node object
+/// exactly once, in depth-first pre-order of first occurrence.
+///
+/// Equivalent to `list(dict.fromkeys(walk_dfs(node)))`, without the
+/// Python-side identity set. Also terminates on cyclic trees, since a
+/// node that has been seen is never expanded again. `node`,
+/// `sequences`, `max_nodes`, `strict`, `into` and `size_hint` behave as
+/// in `walk_dfs`; the budget counts distinct nodes, and nodes shared
+/// between several roots are emitted once.
+#[pyfunction]
+#[pyo3(signature = (
+    node, *, sequences = false, max_nodes = None, strict = true, into = None, size_hint = None,
+))]
+fn walk_unique<'py>(
+    py: Python<'py>,
+    node: Bound<'py, PyAny>,
+    sequences: bool,
+    max_nodes: Option<usize>,
+    strict: bool,
+    into: Option<Bound<'py, PyList>>,
+    size_hint: Option<usize>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let options = WalkOptions {
+        sequences,
+        max_nodes: max_nodes.unwrap_or(usize::MAX),
+        size_hint,
+        ..WalkOptions::default()
+    };
+    with_walker(py, options, |walker, scratch| {
+        walker.roots(&node, strict, "walk_unique", &mut scratch.roots)?;
+        walk_node_unique(
+            walker,
+            &scratch.roots,
+            &mut scratch.stack,
+            &mut scratch.result,
+        )?;
+        scratch.finish(py, into)
+    })
+}
+
+/// Walk the AST rooted at `node` and return its nodes grouped by type:
+/// a dict mapping each node type to the list of nodes of that type.
+///
+/// Same as bucketing `walk_unordered(node)` by `type(n)` in Python, but
+/// the buckets are filled from the walk's own type table, so there is
+/// no per-node dict lookup. Keys appear in the order their type was
+/// first met and each list follows traversal order, which, as in
+/// `walk_unordered`, is unspecified. Exact types are used: a subclass of
+/// `ast.Name` gets its own key. `node`, `sequences`, `detect_cycles`,
+/// `max_nodes` and `strict` behave as in `walk_dfs`.
+#[pyfunction]
+#[pyo3(signature = (
+    node, *, sequences = false, detect_cycles = false, max_nodes = None, strict = true,
+))]
+fn walk_grouped<'py>(
+    py: Python<'py>,
+    node: Bound<'py, PyAny>,
+    sequences: bool,
+    detect_cycles: bool,
+    max_nodes: Option<usize>,
+    strict: bool,
+) -> PyResult<Bound<'py, PyDict>> {
+    let options = WalkOptions {
+        sequences,
+        detect_cycles,
+        max_nodes: max_nodes.unwrap_or(usize::MAX),
+        ..WalkOptions::default()
+    };
+    with_walker(py, options, |walker, scratch| {
+        walker.roots(&node, strict, "walk_grouped", &mut scratch.roots)?;
+        walk_roots(
+            walker,
+            &scratch.roots,
+            false,
+            &mut scratch.stack,
+            &mut scratch.result,
+        )?;
+        group_by_type(
+            py,
+            walker.field_table,
+            &scratch.result,
+            &mut scratch.buckets,
+        )
+    })
+}
+
+/// Encode the AST rooted at `node` as flat integer arrays, for feature
+/// extraction without per-node Python objects.
+///
+/// Returns an `EncodedTree` whose `type_code`, `parent`, `depth`,
+/// `lineno` and `col_offset` attributes are read-only `int32` arrays
+/// supporting the buffer protocol (`numpy.frombuffer(enc.parent,
+/// dtype=numpy.int32)`), one entry per node in `walk_dfs` order.
+/// `type_code` indexes `type_vocabulary()`; `parent` is the index of the
+/// parent node, or -1 for roots; `depth` is 0 for roots. Positions
+/// missing from a node (e.g. `ast.Load()`) are -1. `node`, `sequences`,
+/// `max_nodes` and `strict` behave as in `walk_dfs`; non-AST roots of a
+/// `strict=False` walk get type code -1.
+#[pyfunction]
+#[pyo3(signature = (node, *, sequences = false, max_nodes = None, strict = true))]
+fn encode_tree<'py>(
+    py: Python<'py>,
+    node: Bound<'py, PyAny>,
+    sequences: bool,
+    max_nodes: Option<usize>,
+    strict: bool,
+) -> PyResult<encode::EncodedTree> {
+    let options = WalkOptions {
+        sequences,
+        max_nodes: max_nodes.unwrap_or(usize::MAX),
+        ..WalkOptions::default()
+    };
+    with_walker(py, options, |walker, scratch| {
+        walker.roots(&node, strict, "encode_tree", &mut scratch.roots)?;
+        encode::encode(walker, &scratch.roots)
+    })
+}
+
+/// The node classes behind `encode_tree`'s type codes: code `i` is
+/// `type_vocabulary()[i]`.
+///
+/// Contains the stdlib `ast` classes in a fixed order that depends only
+/// on the Python version, so codes can be stored and compared across
+/// processes. Other `ast.AST` subclasses are encoded with the code of
+/// their nearest stdlib base class (`ast.AST` itself is code 0).
+#[pyfunction]
+fn type_vocabulary(py: Python<'_>) -> PyResult<Bound<'_, PyTuple>> {
+    encode::vocabulary(py)
+}
+
+/// Export the AST rooted at `node` as an Arrow table, for loading into
+/// DuckDB, Polars or pyarrow without a Python-level conversion.
+///
+/// One row per node in depth-first pre-order over `_fields` (the order
+/// of `ast.iter_child_nodes`; `walk_dfs` order unless a node was built
+/// with its fields out of order), with columns `node_id` (the row
+/// number), `parent_id`, `type`, `field` and `index` (where the node
+/// sits in its parent: field name, and position if that field is a
+/// list), `lineno`, `col_offset`, `end_lineno`, `end_col_offset`, and
+/// `value` (`repr()` of an `ast.Constant`'s value). Missing values are
+/// null. The returned object implements the Arrow PyCapsule interface
+/// (`__arrow_c_array__` and `__arrow_c_stream__`). `node`, `max_nodes`
+/// and `strict` behave as in `walk_dfs`. Only built with the `arrow`
+/// cargo feature.
+#[cfg(feature = "arrow")]
+#[pyfunction]
+#[pyo3(signature = (node, *, max_nodes = None, strict = true))]
+fn to_arrow<'py>(
+    py: Python<'py>,
+    node: Bound<'py, PyAny>,
+    max_nodes: Option<usize>,
+    strict: bool,
+) -> PyResult<arrow::NodeTable> {
+    let options = WalkOptions {
+        max_nodes: max_nodes.unwrap_or(usize::MAX),
+        ..WalkOptions::default()
+    };
+    with_walker(py, options, |walker, scratch| {
+        walker.roots(&node, strict, "to_arrow", &mut scratch.roots)?;
+        arrow::to_arrow(walker, &scratch.roots)
+    })
+}
+
+/// Serialize the AST rooted at `node` (or a list of nodes) to a JSON
+/// string.
+///
+/// Each node becomes an object whose `"_type"` is its class name,
+/// followed by its fields in `_fields` order, e.g. `{"_type": "Call",
+/// "func": ..., "args": [...], "keywords": []}`; fields the node doesn't
+/// have are left out. With `include_positions=True` (the default) the
+/// `_attributes` (`lineno`, `col_offset`, ...) follow. Constant values
+/// JSON can't spell are tagged objects: `{"_type": "bytes", "base64":
+/// ...}`, `{"_type": "complex", "real": ..., "imag": ...}`, `{"_type":
+/// "Ellipsis"}`, `{"_type": "float", "value": "inf"}` and `{"_type":
+/// "tuple", "elts": [...]}` (or `"frozenset"`). The text is exactly what
+/// `json.dumps(..., indent=indent)` would produce for the same objects.
+#[pyfunction]
+#[pyo3(signature = (node, include_positions = true, indent = None))]
+fn to_json<'py>(
+    py: Python<'py>,
+    node: Bound<'py, PyAny>,
+    include_positions: bool,
+    indent: Option<usize>,
+) -> PyResult<String> {
+    with_walker(py, WalkOptions::default(), |walker, scratch| {
+        walker.roots(&node, true, "to_json", &mut scratch.roots)?;
+        let mut writer = json::JsonWriter::new(walker, include_positions, indent);
+        writer.value(&node)?;
+        Ok(writer.finish())
+    })
+}
+
+/// Rebuild a tree from `to_json` output.
+///
+/// Node objects are instantiated as the stdlib `ast` class their
+/// `"_type"` names, with the other members as fields and positions, so
+/// a tree serialized with `include_positions=True` can be passed
+/// straight to `compile()`. An unknown `"_type"`, a member that is not
+/// one of the class's `_fields` or `_attributes`, or malformed JSON
+/// raises `ValueError` naming the JSON path of the offending value
+/// (e.g. `$.body[0].value.func`).
+#[pyfunction]
+fn from_json<'py>(py: Python<'py>, text: &str) -> PyResult<Bound<'py, PyAny>> {
+    json::JsonReader::new(py, text)?.read()
+}
+
+/// Serialize the AST rooted at `node` (or a list of nodes) to a compact
+/// binary format, for caching parsed trees on disk.
+///
+/// Fields and positions are stored against `type_vocabulary()` codes,
+/// with varint integers and every string stored once, which makes the
+/// result much smaller and faster to load than a pickle. The data
+/// records the Python version that wrote it, and `loads_binary` rejects
+/// it under any other. Only the stdlib `ast` classes can be serialized;
+/// anything else in the tree raises `TypeError`.
+#[pyfunction]
+fn dumps_binary<'py>(py: Python<'py>, node: Bound<'py, PyAny>) -> PyResult<Bound<'py, PyBytes>> {
+    let data = with_walker(py, WalkOptions::default(), |walker, scratch| {
+        walker.roots(&node, true, "dumps_binary", &mut scratch.roots)?;
+        let mut encoder = binary::Encoder::new(walker);
+        encoder.value(&node)?;
+        Ok(encoder.finish())
+    })?;
+    Ok(PyBytes::new(py, &data))
+}
+
+/// Rebuild a tree from `dumps_binary` output.
+///
+/// Raises `ValueError` if `data` was written by a different Python
+/// version or format version (the cache entry is stale), or is not
+/// `dumps_binary` output at all.
+#[pyfunction]
+fn loads_binary<'py>(py: Python<'py>, data: &[u8]) -> PyResult<Bound<'py, PyAny>> {
+    binary::Decoder::new(py, data)?.read()
+}
+
+/// `ast.dump(node, ...)`, with byte-identical output for the running
+/// Python version, for snapshot tests and logs of large modules.
+///
+/// Arguments are as for `ast.dump`: `indent` may be a number of spaces
+/// or a string, and `show_empty` (keyword-only, default `False`) keeps
+/// `None` and empty-list fields in the output.
+#[pyfunction]
+#[pyo3(signature = (
+    node, annotate_fields = true, include_attributes = false, *, indent = None, show_empty = false,
+))]
+fn dump<'py>(
+    py: Python<'py>,
+    node: Bound<'py, PyAny>,
+    annotate_fields: bool,
+    include_attributes: bool,
+    indent: Option<Bound<'py, PyAny>>,
+    show_empty: bool,
+) -> PyResult<Bound<'py, PyString>> {
+    // `' ' * indent` for a number, as `ast.dump` spells it.
+    let indent = match indent {
+        None => None,
+        Some(indent) => Some(match indent.cast_into::<PyString>() {
+            Ok(indent) => indent,
+            Err(err) => intern!(py, " ").mul(err.into_inner())?.cast_into()?,
+        }),
+    };
+    let options = ast_dump::DumpOptions {
+        annotate_fields,
+        include_attributes,
+        indent,
+        show_empty,
+    };
+    with_walker(py, WalkOptions::default(), |walker, _| {
+        ast_dump::Dumper::new(walker, options)?.dump(&node)
+    })
+}
+
+/// Render the AST rooted at `node` (or a list of nodes) as a Graphviz
+/// DOT `digraph`, e.g. for `dot -Tsvg`.
+///
+/// Each node is a vertex labelled with its type and its scalar fields
+/// (`Name` / `id='x'`, `Constant` / `value=1`; unset optional fields are
+/// left out and long values shortened), plus its
+/// `lineno:col_offset-end_lineno:end_col_offset` span with
+/// `show_positions=True`. Edges run from parent to child and are
+/// labelled with the field, and the index for list fields (`body[0]`).
+/// Vertices are numbered in depth-first pre-order over `_fields` (the
+/// order of `ast.iter_child_nodes`), which is `walk_dfs` order unless a
+/// node was built with its fields out of order. `max_nodes` behaves as
+/// in `walk_dfs`; a tree that contains itself raises `ValueError`.
+#[pyfunction]
+#[pyo3(signature = (node, max_nodes = None, show_positions = false))]
+fn to_dot<'py>(
+    py: Python<'py>,
+    node: Bound<'py, PyAny>,
+    max_nodes: Option<usize>,
+    show_positions: bool,
+) -> PyResult<String> {
+    let options = WalkOptions {
+        max_nodes: max_nodes.unwrap_or(usize::MAX),
+        ..WalkOptions::default()
+    };
+    with_walker(py, options, |walker, scratch| {
+        walker.roots(&node, true, "to_dot", &mut scratch.roots)?;
+        dot::to_dot(walker, &scratch.roots, show_positions)
+    })
+}
+
+/// Render the AST rooted at `node` (or a list of nodes, one after
+/// another) as an S-expression, `(Call (Name print) (Constant 1))`.
+///
+/// Fields are given positionally, in `_fields` order: child nodes as
+/// nested forms, list items spliced in, identifiers bare, other scalars
+/// `repr()`ed. Expression contexts, positions and unset optional fields
+/// are left out, and nodes with nothing else to show (`Add`, `Pass`)
+/// are bare names. Subtrees that fit in `width` columns stay on one
+/// line; the others put each child on its own line, indented by two.
+/// `max_nodes` behaves as in `walk_dfs`; a tree that contains itself
+/// raises `ValueError`.
+#[pyfunction]
+#[pyo3(signature = (node, width = 100, max_nodes = None))]
+fn to_sexpr<'py>(
+    py: Python<'py>,
+    node: Bound<'py, PyAny>,
+    width: usize,
+    max_nodes: Option<usize>,
+) -> PyResult<String> {
+    let options = WalkOptions {
+        max_nodes: max_nodes.unwrap_or(usize::MAX),
+        ..WalkOptions::default()
+    };
+    with_walker(py, options, |walker, scratch| {
+        walker.roots(&node, true, "to_sexpr", &mut scratch.roots)?;
+        sexpr::to_sexpr(walker, &scratch.roots, width)
+    })
+}
+
+/// `ast.compare(a, b)` (new in Python 3.14) on every supported version:
+/// whether two trees are structurally equal, positions aside.
+///
+/// Nodes must be of the same type with equal fields, lists equal item by
+/// item, and other values of the same type and `==`. `a` and `b` may
+/// also be lists of nodes. `compare_attributes=True` compares positions
+/// too. With `return_path=True` the result is `None` for equal trees and
+/// otherwise where they first differ, e.g. `"$.body[0].value.id"`.
+#[pyfunction]
+#[pyo3(signature = (a, b, /, *, compare_attributes = false, return_path = false))]
+fn ast_equal<'py>(
+    py: Python<'py>,
+    a: Bound<'py, PyAny>,
+    b: Bound<'py, PyAny>,
+    compare_attributes: bool,
+    return_path: bool,
+) -> PyResult<Bound<'py, PyAny>> {
+    with_walker(py, WalkOptions::default(), |walker, scratch| {
+        walker.roots(&a, true, "ast_equal", &mut scratch.roots)?;
+        walker.roots(&b, true, "ast_equal", &mut scratch.roots)?;
+        let mismatch = compare::Comparer::new(walker, compare_attributes).compare(&a, &b)?;
+        if return_path {
+            Ok(mismatch.into_pyobject(py)?.into_any())
+        } else {
+            Ok(PyBool::new(py, mismatch.is_none()).to_owned().into_any())
+        }
+    })
+}
+
+/// A structural hash of the subtree under every node of `node` (or of a
+/// list of nodes), in `walk_dfs` order — `zip` the two to group clones.
+///
+/// Subtrees equal under `ast_equal` hash equally, whatever order their
+/// fields were set in; positions are ignored. The hashes are 64-bit,
+/// computed in one post-order pass, and don't change from run to run,
+/// so a constant whose hash would (an object with the default identity
+/// hash) or that has no hash at all, and isn't a `list`, `set` or
+/// `dict`, raises `TypeError`.
+#[pyfunction]
+fn subtree_hashes<'py>(py: Python<'py>, node: Bound<'py, PyAny>) -> PyResult<Bound<'py, PyList>> {
+    with_walker(py, WalkOptions::default(), |walker, scratch| {
+        walker.roots(&node, true, "subtree_hashes", &mut scratch.roots)?;
+        scratch.result.clear();
+        walk_node_dfs(
+            walker,
+            &scratch.roots,
+            &mut scratch.stack,
+            &mut scratch.result,
+        )?;
+        let hashes = merkle::SubtreeHasher::new(walker).hashes(&scratch.roots, &scratch.result)?;
+        PyList::new(py, hashes)
+    })
+}
+
+/// Walk many roots in one call — e.g. the top-level statements an
+/// incremental analyzer needs to re-walk after an edit — paying the
+/// per-call setup once.
+///
+/// `nodes` is any iterable of AST nodes. With `flatten=True` (the
+/// default) every root seeds the same traversal and one flat list comes
+/// back; with `flatten=False` the result is a list of per-root lists, in
+/// input order. Traversal order follows `walk_unordered`, or `walk_dfs`
+/// with `dfs=True`; the remaining keyword arguments behave as in
+/// `walk_dfs`, and `max_nodes` budgets the call as a whole. `into` is
+/// only accepted with `flatten=True`.
+#[pyfunction]
+#[pyo3(signature = (
+    nodes, flatten = true, *, dfs = false, sequences = false, detect_cycles = false,
+    max_nodes = None, strict = true, into = None, size_hint = None,
+))]
+#[allow(clippy::too_many_arguments)]
+fn walk_many<'py>(
+    py: Python<'py>,
+    nodes: Bound<'py, PyAny>,
+    flatten: bool,
+    dfs: bool,
+    sequences: bool,
+    detect_cycles: bool,
+    max_nodes: Option<usize>,
+    strict: bool,
+    into: Option<Bound<'py, PyList>>,
+    size_hint: Option<usize>,
+) -> PyResult<Bound<'py, PyAny>> {
+    let options = WalkOptions {
+        sequences,
+        detect_cycles,
+        max_nodes: max_nodes.unwrap_or(usize::MAX),
+        size_hint,
+        ..WalkOptions::default()
+    };
+    if into.is_some() && !flatten {
+        return Err(PyValueError::new_err(
+            "walk_many() only accepts into= with flatten=True",
+        ));
+    }
+    with_walker(py, options, |walker, scratch| {
+        // Own every root for the whole call: `nodes` may be a generator
+        // yielding nodes that nothing else references.
+        let items = nodes.try_iter()?.collect::<PyResult<Vec<_>>>()?;
+        for (i, item) in items.iter().enumerate() {
+            walker.check_root_item(item, i, strict, "walk_many")?;
+        }
+        scratch.roots.extend(items.iter().map(Bound::as_ptr));
+
+        let Scratch {
+            roots,
+            stack,
+            result,
+            ..
+        } = &mut *scratch;
+        if flatten {
+            walk_roots(walker, roots, dfs, stack, result)?;
+            return scratch.finish(py, into);
+        }
+
+        // One traversal per root into the shared buffer; the recorded
+        // offsets split it back into per-root lists.
+        let mut offsets = Vec::with_capacity(roots.len() + 1);
+        offsets.push(0);
+        for root in roots.iter() {
+            walk_roots(walker, std::slice::from_ref(root), dfs, stack, result)?;
+            offsets.push(result.len());
+        }
+        let per_root = PyList::empty(py);
+        for span in offsets.windows(2) {
+            per_root.append(vec_into_pylist(py, &result[span[0]..span[1]])?)?;
+        }
+        Ok(per_root.into_any())
+    })
+}
+
+/// Prefetch distances `calibrate()` tries by default.
+const CALIBRATION_CANDIDATES: [usize; 7] = [1, 2, 4, 6, 8, 12, 16];
+
+/// Copies of [`CALIBRATION_FUNCTION`] in the calibration module: ~47k
+/// nodes, the size of a large real module. Their instance dicts take
+/// several MB, well past a typical L2, so the prefetches have misses to
+/// hide; parsing the module is most of `calibrate`'s run time.
+const CALIBRATION_FUNCTIONS: usize = 500;
+
+/// One function's worth of the synthetic calibration module, with the
+/// mix of statements, calls, operators and literals of ordinary code.
+const CALIBRATION_FUNCTION: &str = "
+def f{i}(a, b=None, *args, **kwargs):
+    total = [a + b * {i}, {'key': a, 'other': -b}, (a, b)]
+    for item in args:
+        if item is not None and item > a:
+            total.append(item.value(a, b)[0])
+        else:
+            kwargs[str(item)] = f'{item!r} {a}'
+    return total
+";
+
+/// Build the synthetic module `calibrate` walks.
+fn calibration_tree(py: Python<'_>) -> PyResult<Bound<'_, PyAny>> {
+    let mut source = String::new();
+    for i in 0..CALIBRATION_FUNCTIONS {
+        source.push_str(&CALIBRATION_FUNCTION.replace("{i}", &i.to_string()));
+    }
+    py.import("ast")?.call_method1("parse", (source,))
+}
+
+/// Benchmark `walk_unordered` with each of `candidates` as the prefetch
+/// distance (`batch`) on a synthetic module, make the fastest the
+/// process-wide default, and return it.
+///
+/// Rounds interleave the candidates so that frequency scaling and other
+/// load affect them alike; each candidate is scored by its best round.
+/// Takes a fraction of a second — call it once at startup.
+#[pyfunction]
+#[pyo3(signature = (candidates = None, *, rounds = 7))]
+fn calibrate(py: Python<'_>, candidates: Option<Vec<usize>>, rounds: usize) -> PyResult<usize> {
+    let candidates = candidates.unwrap_or_else(|| CALIBRATION_CANDIDATES.to_vec());
+    if candidates.is_empty() {
+        return Err(PyValueError::new_err(
+            "calibrate() needs at least one candidate batch size",
+        ));
+    }
+    for &batch in &candidates {
+        check_batch(batch)?;
+    }
+    if rounds == 0 {
+        return Err(PyValueError::new_err("rounds must be at least 1"));
+    }
+
+    let tree = calibration_tree(py)?;
+    let root = tree.as_ptr();
+    let mut best = vec![Duration::MAX; candidates.len()];
+    with_walker(py, WalkOptions::default(), |walker, scratch| {
+        for _ in 0..rounds {
+            for (&batch, best) in candidates.iter().zip(best.iter_mut()) {
+                walker.options.batch = batch;
+                scratch.result.clear();
+                let start = Instant::now();
+                walk_node_unordered(walker, &[root], &mut scratch.stack, &mut scratch.result)?;
+                *best = (*best).min(start.elapsed());
+            }
+        }
+        Ok(())
+    })?;
+
+    let (winner, _) = candidates
+        .iter()
+        .zip(&best)
+        .min_by_key(|&(_, elapsed)| *elapsed)
+        .unwrap();
+    DEFAULT_BATCH.store(*winner, Ordering::Relaxed);
+    Ok(*winner)
+}
+
 static DEPRECATED_WALK_WARNED: AtomicBool = AtomicBool::new(false);
 
 /// Deprecated. Use `walk_dfs` for explicit depth-first order or
-/// `walk_unordered` for the faster order-agnostic variant.
+/// `walk_unordered` for the order-agnostic variant.
 ///
 /// Emits a `DeprecationWarning` once per process on the first call, then
 /// delegates to `walk_dfs`. The warning is gated behind an atomic flag
@@ -529,11 +2164,11 @@ fn walk<'py>(py: Python<'py>, node: Bound<'py, PyAny>) -> PyResult<Bound<'py, Py
         PyErr::warn(
             py,
             &category,
-            c"fast_walk.walk is deprecated; use walk_dfs for strict depth-first order or walk_unordered for the faster order-agnostic variant",
+            c"fast_walk.walk is deprecated; use walk_dfs for strict depth-first order or walk_unordered for the order-agnostic variant",
             1,
         )?;
     }
-    walk_dfs(py, node)
+    walk_dfs(py, node, false, false, None, true, None, None)
 }
 
 /// Benchmarking-only. Traverse the AST and return the node count without
@@ -541,13 +2176,10 @@ fn walk<'py>(py: Python<'py>, node: Bound<'py, PyAny>) -> PyResult<Bound<'py, Py
 /// cost for profiling deltas.
 #[pyfunction]
 fn _walk_count<'py>(py: Python, node: Bound<'py, PyAny>) -> PyResult<usize> {
-    let base = resolve_base_types(py)?;
-    let py_list_type = PyList::type_object_raw(py);
     let node_ptr = node.as_ptr();
-    with_field_table(py, |table| {
-        let mut result_list = Vec::new();
-        walk_node_dfs(node_ptr, base, py_list_type, table, &mut result_list)?;
-        Ok(result_list.len())
+    with_walker(py, WalkOptions::default(), |walker, scratch| {
+        walk_node_dfs(walker, &[node_ptr], &mut scratch.stack, &mut scratch.result)?;
+        Ok(scratch.result.len())
     })
 }
 
@@ -556,7 +2188,30 @@ fn fast_walk(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(walk, m)?)?;
     m.add_function(wrap_pyfunction!(walk_dfs, m)?)?;
     m.add_function(wrap_pyfunction!(walk_unordered, m)?)?;
+    m.add_function(wrap_pyfunction!(walk_unique, m)?)?;
+    m.add_function(wrap_pyfunction!(walk_grouped, m)?)?;
+    m.add_function(wrap_pyfunction!(walk_many, m)?)?;
+    m.add_function(wrap_pyfunction!(encode_tree, m)?)?;
+    m.add_function(wrap_pyfunction!(type_vocabulary, m)?)?;
+    m.add_function(wrap_pyfunction!(to_json, m)?)?;
+    m.add_function(wrap_pyfunction!(from_json, m)?)?;
+    m.add_function(wrap_pyfunction!(dumps_binary, m)?)?;
+    m.add_function(wrap_pyfunction!(loads_binary, m)?)?;
+    m.add_function(wrap_pyfunction!(dump, m)?)?;
+    m.add_function(wrap_pyfunction!(to_dot, m)?)?;
+    m.add_function(wrap_pyfunction!(to_sexpr, m)?)?;
+    m.add_function(wrap_pyfunction!(ast_equal, m)?)?;
+    m.add_function(wrap_pyfunction!(subtree_hashes, m)?)?;
+    m.add_function(wrap_pyfunction!(calibrate, m)?)?;
     m.add_function(wrap_pyfunction!(_walk_count, m)?)?;
+    m.add("TreeTooLarge", m.py().get_type::<TreeTooLarge>())?;
+    m.add_class::<encode::Int32Array>()?;
+    m.add_class::<encode::EncodedTree>()?;
+    #[cfg(feature = "arrow")]
+    {
+        m.add_function(wrap_pyfunction!(to_arrow, m)?)?;
+        m.add_class::<arrow::NodeTable>()?;
+    }
     Ok(())
 }
 
@@ -595,4 +2250,79 @@ mod tests {
             assert_eq!(values.len(), 3);
         });
     }
+
+    #[test]
+    fn test_field_table_grows_past_initial_size() {
+        // Synthetic, 16-byte-aligned "type pointers" — never dereferenced.
+        // The second batch all land on the same initial slot as `1 << 4`,
+        // to exercise long probe chains across a resize.
+        let ptrs: Vec<*mut PyTypeObject> = (1..=5000u64)
+            .map(|i| (i << 4) as *mut PyTypeObject)
+            .chain((1..=64u64).map(|i| ((i << 24) | 0x10) as *mut PyTypeObject))
+            .collect();
+
+        let mut table = FieldTable::new();
+        for (i, &ptr) in ptrs.iter().enumerate() {
+            table.insert(ptr, (i % 200) as u8 + 1);
+        }
+
+        assert!(table.keys.len() >= 2 * table.len);
+        for (i, &ptr) in ptrs.iter().enumerate() {
+            assert_eq!(table.lookup(ptr), (i % 200) as u8 + 1);
+        }
+        assert_eq!(table.lookup((1u64 << 40) as *mut PyTypeObject), 0);
+    }
+
+    #[test]
+    fn test_field_table_grow_keeps_type_codes() {
+        let ptrs: Vec<*mut PyTypeObject> = (1..=1000u64)
+            .map(|i| (i << 4) as *mut PyTypeObject)
+            .collect();
+        let mut table = FieldTable::new();
+        for (i, &ptr) in ptrs.iter().enumerate() {
+            let slot = table.insert(ptr, 1);
+            table.codes[slot] = i as u16;
+        }
+        for (i, &ptr) in ptrs.iter().enumerate() {
+            let slot = table.slot_for(ptr).unwrap();
+            assert_eq!(table.codes[slot], i as u16);
+        }
+    }
+
+    #[test]
+    fn test_field_table_reinsert_updates_in_place() {
+        let ptr = 0x1000 as *mut PyTypeObject;
+        let mut table = FieldTable::new();
+        table.insert(ptr, 4);
+        table.insert(ptr, 6);
+        assert_eq!(table.len, 1);
+        assert_eq!(table.lookup(ptr), 6);
+    }
+
+    #[test]
+    fn test_scratch_presizes_from_previous_walk() {
+        let mut scratch = Scratch::new();
+        scratch
+            .result
+            .extend(std::iter::repeat_n(std::ptr::null_mut(), 5000));
+        scratch.reset();
+        assert_eq!(scratch.last_len, 5000);
+
+        let mut scratch = Scratch {
+            last_len: scratch.last_len,
+            ..Scratch::new()
+        };
+        scratch.presize(&WalkOptions::default());
+        assert!(scratch.result.capacity() >= 5000);
+
+        // An explicit hint wins, but is capped by the budget.
+        let mut scratch = Scratch::new();
+        let options = WalkOptions {
+            size_hint: Some(usize::MAX / 2),
+            max_nodes: 100,
+            ..WalkOptions::default()
+        };
+        scratch.presize(&options);
+        assert!((101..5000).contains(&scratch.result.capacity()));
+    }
 }
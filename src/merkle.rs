@@ -0,0 +1,355 @@
+// Structural subtree hashes for `subtree_hashes`.
+//
+// One depth-first pass: entering a node records what its hash depends on
+// — its type, then per field either a scalar's hash or a placeholder for
+// a child node — and leaving it (post-order, once every child has its
+// hash) folds that record into the node's hash. Positions are never
+// read, so structurally equal subtrees hash equally wherever they are.
+//
+// The pass goes by `_fields`, like `ast_equal`, so that nodes built with
+// their fields in another order still hash alike. `walk_dfs` follows the
+// order the fields are stored in instead, so the results are handed out
+// by node identity, in the order of the walk.
+//
+// The hash is a fixed 64-bit mix rather than Python's `hash()`, whose
+// `str` hashing is randomized per process: results are the same on
+// every run and can be kept across runs.
+
+use std::collections::HashMap;
+use std::hash::BuildHasherDefault;
+
+use pyo3::exceptions::PyTypeError;
+use pyo3::ffi::{self, PyObject, PyTypeObject};
+use pyo3::prelude::*;
+use pyo3::types::{
+    PyBool, PyBytes, PyComplex, PyDict, PyFloat, PyFrozenSet, PyInt, PyList, PySet, PyString,
+    PyTuple, PyType,
+};
+
+use crate::fields::NodeFields;
+use crate::{PtrHasher, RecursionGuard, Walker, utf8};
+
+// What each hashed record starts with, so that e.g. a missing field, an
+// empty list and `None` don't hash alike.
+const NODE: u64 = 1;
+const TYPE: u64 = 2;
+const MISSING: u64 = 3;
+const LIST: u64 = 4;
+const NONE: u64 = 5;
+const ELLIPSIS: u64 = 6;
+const STR: u64 = 7;
+const BYTES: u64 = 8;
+const TUPLE: u64 = 9;
+const FROZENSET: u64 = 10;
+const NAN: u64 = 11;
+/// A number inside a tuple or frozenset, where `1 == 1.0 == True`.
+const NUMBER: u64 = 12;
+const BOOL: u64 = 13;
+const INT: u64 = 14;
+const FLOAT: u64 = 15;
+const COMPLEX: u64 = 16;
+const OTHER: u64 = 17;
+const DICT: u64 = 18;
+const ENTRY: u64 = 19;
+
+/// murmur3's 64-bit finalizer: a bijection that spreads every input bit
+/// over the whole word.
+fn mix(mut x: u64) -> u64 {
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    x ^ (x >> 33)
+}
+
+struct Digest(u64);
+
+impl Digest {
+    fn new(tag: u64) -> Self {
+        Self(mix(tag))
+    }
+
+    fn word(&mut self, word: u64) -> &mut Self {
+        self.0 = mix(self.0.rotate_left(5) ^ word);
+        self
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        for chunk in bytes.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.word(u64::from_le_bytes(word));
+        }
+        self.word(bytes.len() as u64)
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// What a node's hash is made of, in field order.
+enum Item {
+    Word(u64),
+    /// The next child node's hash.
+    Child,
+}
+
+enum Work<'py> {
+    Enter(Bound<'py, PyAny>),
+    /// All of node `id`'s children are done; its record starts at
+    /// `items[start]`.
+    Exit {
+        id: usize,
+        start: usize,
+    },
+}
+
+pub(crate) struct SubtreeHasher<'w, 'py> {
+    py: Python<'py>,
+    walker: &'w Walker<'w, 'py>,
+    fields: NodeFields<'py>,
+    types: HashMap<*mut PyTypeObject, u64, BuildHasherDefault<PtrHasher>>,
+}
+
+impl<'w, 'py> SubtreeHasher<'w, 'py> {
+    pub(crate) fn new(walker: &'w Walker<'w, 'py>) -> Self {
+        Self {
+            py: walker.py,
+            walker,
+            fields: NodeFields::new(walker.py),
+            types: HashMap::default(),
+        }
+    }
+
+    /// The hash of every node under `roots`, in `order`: the nodes
+    /// `walk_dfs` returns for them.
+    pub(crate) fn hashes(
+        &mut self,
+        roots: &[*mut PyObject],
+        order: &[*mut PyObject],
+    ) -> PyResult<Vec<u64>> {
+        let (nodes, hashes) = self.post_order(roots)?;
+        let by_node: HashMap<*mut PyObject, u64, BuildHasherDefault<PtrHasher>> =
+            nodes.iter().map(|node| node.as_ptr()).zip(hashes).collect();
+        order
+            .iter()
+            .map(|&node| match by_node.get(&node) {
+                Some(&hash) => Ok(hash),
+                // Only reachable through a non-field dict entry, which
+                // the pass above doesn't look at.
+                None => Ok(self.post_order(&[node])?.1[0]),
+            })
+            .collect()
+    }
+
+    /// Every node under `roots` in `_fields` pre-order, with its hash.
+    /// The nodes are kept alive so that their addresses stay theirs.
+    fn post_order(
+        &mut self,
+        roots: &[*mut PyObject],
+    ) -> PyResult<(Vec<Bound<'py, PyAny>>, Vec<u64>)> {
+        let py = self.py;
+        let mut nodes = Vec::new();
+        let mut hashes = Vec::new();
+        // Number of nodes in each node's subtree, to step from one child
+        // to the next in `hashes`.
+        let mut sizes = Vec::new();
+        let mut items = Vec::new();
+        let mut stack: Vec<Work<'py>> = roots
+            .iter()
+            .rev()
+            // SAFETY: the roots are alive for the whole call.
+            .map(|&root| Work::Enter(unsafe { Bound::from_borrowed_ptr(py, root) }))
+            .collect();
+        let mut children = Vec::new();
+
+        while let Some(work) = stack.pop() {
+            match work {
+                Work::Enter(node) => {
+                    let id = hashes.len();
+                    nodes.push(node.clone());
+                    hashes.push(0);
+                    sizes.push(0);
+                    let start = items.len();
+                    items.push(Item::Word(self.type_hash(&node.get_type())?));
+                    for name in self.fields.names(&node)?.iter() {
+                        let Some(value) = self.fields.get(&node, name)? else {
+                            items.push(Item::Word(MISSING));
+                            continue;
+                        };
+                        if let Ok(list) = value.cast::<PyList>() {
+                            items.push(Item::Word(LIST));
+                            items.push(Item::Word(list.len() as u64));
+                            for item in list.iter() {
+                                if self.walker.is_root(&item) {
+                                    items.push(Item::Child);
+                                    children.push(item);
+                                } else {
+                                    items.push(Item::Word(self.value(&item, true)?));
+                                }
+                            }
+                        } else if self.walker.is_root(&value) {
+                            items.push(Item::Child);
+                            children.push(value);
+                        } else {
+                            items.push(Item::Word(self.value(&value, true)?));
+                        }
+                    }
+                    stack.push(Work::Exit { id, start });
+                    stack.extend(children.drain(..).rev().map(Work::Enter));
+                }
+                Work::Exit { id, start } => {
+                    let mut digest = Digest::new(NODE);
+                    let mut child = id + 1;
+                    for item in items.drain(start..) {
+                        match item {
+                            Item::Word(word) => digest.word(word),
+                            Item::Child => {
+                                let hash = hashes[child];
+                                child += sizes[child];
+                                digest.word(hash)
+                            }
+                        };
+                    }
+                    hashes[id] = digest.finish();
+                    sizes[id] = hashes.len() - id;
+                }
+            }
+        }
+        Ok((nodes, hashes))
+    }
+
+    /// Node types hash by `__module__` and `__qualname__`.
+    fn type_hash(&mut self, class: &Bound<'py, PyType>) -> PyResult<u64> {
+        let key = class.as_type_ptr();
+        if let Some(&hash) = self.types.get(&key) {
+            return Ok(hash);
+        }
+        let hash = Digest::new(TYPE)
+            .bytes(&utf8(&class.module()?)?)
+            .bytes(&utf8(&class.qualname()?)?)
+            .finish();
+        self.types.insert(key, hash);
+        Ok(hash)
+    }
+
+    /// A scalar field value. Values equal under `type(a) is type(b) and
+    /// a == b` — `ast_equal`'s test — hash equally; `typed` is off inside
+    /// containers, whose items compare with plain `==`.
+    ///
+    /// Containers are hashed by their items, numbers and strings by
+    /// value. Anything else must have a hash of its own, not the
+    /// identity-based default, which would change from run to run.
+    fn value(&mut self, value: &Bound<'py, PyAny>, typed: bool) -> PyResult<u64> {
+        let py = self.py;
+        if value.is_none() {
+            return Ok(Digest::new(NONE).finish());
+        }
+        if value.is(py.Ellipsis()) {
+            return Ok(Digest::new(ELLIPSIS).finish());
+        }
+        if let Ok(text) = value.cast::<PyString>() {
+            return Ok(Digest::new(STR).bytes(&utf8(text)?).finish());
+        }
+        if let Ok(bytes) = value.cast::<PyBytes>() {
+            return Ok(Digest::new(BYTES).bytes(bytes.as_bytes()).finish());
+        }
+        if let Ok(tuple) = value.cast::<PyTuple>() {
+            let _guard = RecursionGuard::enter(py)?;
+            let mut digest = Digest::new(TUPLE);
+            digest.word(tuple.len() as u64);
+            for item in tuple.iter() {
+                digest.word(self.value(&item, false)?);
+            }
+            return Ok(digest.finish());
+        }
+        if let Ok(list) = value.cast::<PyList>() {
+            let _guard = RecursionGuard::enter(py)?;
+            let mut digest = Digest::new(LIST);
+            digest.word(list.len() as u64);
+            for item in list.iter() {
+                digest.word(self.value(&item, false)?);
+            }
+            return Ok(digest.finish());
+        }
+        // Iteration order isn't part of a set's or dict's value: combine
+        // the items' hashes commutatively. `set` and `frozenset` share a
+        // tag, since they compare equal.
+        if let Ok(set) = value.cast::<PyFrozenSet>() {
+            let _guard = RecursionGuard::enter(py)?;
+            let mut sum = 0u64;
+            for item in set.iter() {
+                sum = sum.wrapping_add(mix(self.value(&item, false)?));
+            }
+            return Ok(Digest::new(FROZENSET)
+                .word(set.len() as u64)
+                .word(sum)
+                .finish());
+        }
+        if let Ok(set) = value.cast::<PySet>() {
+            let _guard = RecursionGuard::enter(py)?;
+            let mut sum = 0u64;
+            for item in set.iter() {
+                sum = sum.wrapping_add(mix(self.value(&item, false)?));
+            }
+            return Ok(Digest::new(FROZENSET)
+                .word(set.len() as u64)
+                .word(sum)
+                .finish());
+        }
+        if let Ok(dict) = value.cast::<PyDict>() {
+            let _guard = RecursionGuard::enter(py)?;
+            let mut sum = 0u64;
+            for (key, item) in dict.iter() {
+                let entry = Digest::new(ENTRY)
+                    .word(self.value(&key, false)?)
+                    .word(self.value(&item, false)?)
+                    .finish();
+                sum = sum.wrapping_add(entry);
+            }
+            return Ok(Digest::new(DICT).word(dict.len() as u64).word(sum).finish());
+        }
+
+        let tag = if value.is_instance_of::<PyBool>() {
+            BOOL
+        } else if value.is_instance_of::<PyInt>() {
+            INT
+        } else if let Ok(float) = value.cast::<PyFloat>() {
+            // `hash(nan)` is the object's identity; NaNs never compare
+            // equal anyway.
+            if float.value().is_nan() {
+                return Ok(Digest::new(NAN).finish());
+            }
+            FLOAT
+        } else if let Ok(complex) = value.cast::<PyComplex>() {
+            if complex.real().is_nan() || complex.imag().is_nan() {
+                return Ok(Digest::new(NAN).finish());
+            }
+            COMPLEX
+        } else {
+            OTHER
+        };
+        if tag == OTHER {
+            let class = value.get_type();
+            // SAFETY: reading the hash slot of live type objects.
+            let [own, object] = unsafe {
+                [class.as_type_ptr(), &raw mut ffi::PyBaseObject_Type]
+                    .map(|t| (*t).tp_hash.map(|hash| hash as usize))
+            };
+            let default_hash = own == object;
+            if default_hash || value.hash().is_err() {
+                return Err(PyTypeError::new_err(format!(
+                    "subtree_hashes() can't hash a value of type {}: it has no \
+                     hash of its own that is the same on every run",
+                    class.fully_qualified_name()?
+                )));
+            }
+        }
+        // Python's numeric hash is not randomized, and is equal across
+        // `int`, `float` and `complex` for equal values.
+        let hash = value.hash()? as u64;
+        let tag = if typed || tag == OTHER { tag } else { NUMBER };
+        Ok(Digest::new(tag).word(hash).finish())
+    }
+}
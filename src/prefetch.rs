@@ -0,0 +1,77 @@
+// Software prefetch hints for the batched `walk_unordered` loop.
+//
+// Stable Rust has no portable prefetch (`core::intrinsics::prefetch_*` is
+// unstable), so each target gets its native "prefetch for read into L1"
+// instruction: the `_mm_prefetch` intrinsic on x86, inline assembly
+// elsewhere. Prefetches never fault, so any address is acceptable.
+
+/// Hint the CPU to pull the cache line holding `ptr` into L1 for a
+/// read. No-op on targets without a known prefetch instruction — the
+/// walk runs identically there, just without the cache-miss hiding.
+#[inline(always)]
+pub unsafe fn prefetch_l1(ptr: *const u8) {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        std::arch::x86_64::_mm_prefetch(ptr as *const i8, std::arch::x86_64::_MM_HINT_T0);
+    }
+    #[cfg(all(target_arch = "x86", target_feature = "sse"))]
+    unsafe {
+        std::arch::x86::_mm_prefetch(ptr as *const i8, std::arch::x86::_MM_HINT_T0);
+    }
+    // PRFM PLDL1KEEP: prefetch for load, L1, temporal.
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        std::arch::asm!(
+            "prfm pldl1keep, [{ptr}]",
+            ptr = in(reg) ptr,
+            options(nostack, preserves_flags, readonly),
+        );
+    }
+    #[cfg(target_arch = "arm")]
+    unsafe {
+        std::arch::asm!(
+            "pld [{ptr}]",
+            ptr = in(reg) ptr,
+            options(nostack, preserves_flags, readonly),
+        );
+    }
+    // DCBT with RA=0 touches the line at the effective address RB.
+    #[cfg(target_arch = "powerpc64")]
+    unsafe {
+        std::arch::asm!(
+            "dcbt 0, {ptr}",
+            ptr = in(reg) ptr,
+            options(nostack, preserves_flags, readonly),
+        );
+    }
+    // PFD code 1 = prefetch for fetch. r0 as a base register reads as
+    // zero, hence `reg_addr`.
+    #[cfg(target_arch = "s390x")]
+    unsafe {
+        std::arch::asm!(
+            "pfd 1, 0({ptr})",
+            ptr = in(reg_addr) ptr,
+            options(nostack, preserves_flags, readonly),
+        );
+    }
+    #[cfg(target_arch = "loongarch64")]
+    unsafe {
+        std::arch::asm!(
+            "preld 0, {ptr}, 0",
+            ptr = in(reg) ptr,
+            options(nostack, preserves_flags, readonly),
+        );
+    }
+    #[cfg(not(any(
+        target_arch = "x86_64",
+        all(target_arch = "x86", target_feature = "sse"),
+        target_arch = "aarch64",
+        target_arch = "arm",
+        target_arch = "powerpc64",
+        target_arch = "s390x",
+        target_arch = "loongarch64",
+    )))]
+    {
+        let _ = ptr;
+    }
+}
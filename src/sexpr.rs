@@ -0,0 +1,263 @@
+// S-expression rendering for `to_sexpr`.
+//
+// One field-aware depth-first pass flattens a tree into a document of
+// `Token`s: a node with something to show is a group `(Type child ...)`,
+// its children separated by `Line`s; a node with nothing to show is just
+// its type name. The document is then laid out Wadler-style: a group
+// stays on one line if it fits in the remaining width together with the
+// `)`s that directly follow it, otherwise each of its children starts a
+// line of its own, indented two columns past the group's `(`.
+//
+// Like `to_dot`, the pass keeps its path from the root and raises
+// `ValueError` on a tree that contains itself.
+
+use pyo3::ffi::PyObject;
+use pyo3::intern;
+use pyo3::prelude::*;
+use pyo3::types::{PyList, PyString};
+
+use crate::fields::{NodeFields, is_identifier};
+use crate::{PtrSet, Walker, cycle_error};
+
+/// Extra indentation of a broken group's children.
+const INDENT: usize = 2;
+
+enum Token {
+    /// Start of a group; `end` is the index of its `Close` and `width`
+    /// the length of the group on a single line.
+    Open {
+        end: usize,
+        width: usize,
+    },
+    Close,
+    /// The next `len` bytes of the text, `width` characters wide.
+    Text {
+        len: usize,
+        width: usize,
+    },
+    /// A space in a group laid out flat, a line break otherwise.
+    Line,
+}
+
+enum Work<'py> {
+    /// A node and its number of ancestors.
+    Node(Bound<'py, PyAny>, usize),
+    Atom(String),
+    Line,
+    /// End of the group opened at `tokens[open]`, which started `start`
+    /// characters into the flat rendering.
+    Close {
+        open: usize,
+        start: usize,
+    },
+}
+
+#[derive(Default)]
+struct Document {
+    tokens: Vec<Token>,
+    text: String,
+    /// Length of the whole document so far, laid out flat.
+    flat: usize,
+}
+
+impl Document {
+    fn push_text(&mut self, text: &str) {
+        let width = text.chars().count();
+        self.text.push_str(text);
+        self.tokens.push(Token::Text {
+            len: text.len(),
+            width,
+        });
+        self.flat += width;
+    }
+
+    fn push_line(&mut self) {
+        self.tokens.push(Token::Line);
+        self.flat += 1;
+    }
+
+    fn clear(&mut self) {
+        self.tokens.clear();
+        self.text.clear();
+        self.flat = 0;
+    }
+
+    /// Append the document, laid out to fit in `width` columns where
+    /// possible, to `out`.
+    fn layout(&self, width: usize, out: &mut String) {
+        // `tail[i]`: how far the text from token `i` up to the next
+        // `Line` extends a line, i.e. the `)`s a group must leave room for.
+        let mut tail = vec![0; self.tokens.len() + 1];
+        for (i, token) in self.tokens.iter().enumerate().rev() {
+            tail[i] = match token {
+                Token::Line => 0,
+                Token::Text { width, .. } => width + tail[i + 1],
+                Token::Open { .. } | Token::Close => tail[i + 1],
+            };
+        }
+
+        let mut column = 0;
+        let mut offset = 0;
+        // Column of the `(` of each enclosing broken group.
+        let mut broken = Vec::new();
+        // `Close` of the outermost group being laid out flat.
+        let mut flat_until = None;
+        for (i, token) in self.tokens.iter().enumerate() {
+            match *token {
+                Token::Open { end, width: flat } => {
+                    if flat_until.is_none() {
+                        if column + flat + tail[end + 1] <= width {
+                            flat_until = Some(end);
+                        } else {
+                            broken.push(column);
+                        }
+                    }
+                }
+                Token::Close => {
+                    if flat_until == Some(i) {
+                        flat_until = None;
+                    } else if flat_until.is_none() {
+                        broken.pop();
+                    }
+                }
+                Token::Text { len, width } => {
+                    out.push_str(&self.text[offset..offset + len]);
+                    offset += len;
+                    column += width;
+                }
+                Token::Line => {
+                    if flat_until.is_some() {
+                        out.push(' ');
+                        column += 1;
+                    } else {
+                        column = broken.last().map_or(0, |&open| open + INDENT);
+                        out.push('\n');
+                        out.extend(std::iter::repeat_n(' ', column));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A scalar field value: identifiers bare (`print`), anything else as
+/// its `repr()`. `Constant.value` is always `repr()`ed, so that
+/// `(Constant 'x')` can't be mistaken for a name.
+fn atom(value: &Bound<'_, PyAny>, field: &str) -> PyResult<String> {
+    if field != "value"
+        && let Ok(text) = value.cast::<PyString>()
+        && let Ok(text) = text.to_str()
+        && is_identifier(text)
+    {
+        return Ok(text.to_owned());
+    }
+    Ok(value.repr()?.to_string_lossy().into_owned())
+}
+
+/// Render `roots` as S-expressions, one after another on separate lines.
+pub(crate) fn to_sexpr(
+    walker: &mut Walker<'_, '_>,
+    roots: &[*mut PyObject],
+    width: usize,
+) -> PyResult<String> {
+    let py = walker.py;
+    // `Load`/`Store`/`Del` only restate where the expression is.
+    let expr_context = py
+        .import(intern!(py, "ast"))?
+        .getattr(intern!(py, "expr_context"))?;
+    let mut fields = NodeFields::new(py);
+    let mut document = Document::default();
+    let mut out = String::new();
+    let mut stack = Vec::new();
+    let mut children = Vec::new();
+    let mut path: Vec<Bound<'_, PyAny>> = Vec::new();
+    let mut on_path = PtrSet::default();
+    let mut count = 0;
+
+    for (i, &root) in roots.iter().enumerate() {
+        // SAFETY: the roots are alive for the whole call.
+        stack.push(Work::Node(unsafe { Bound::from_borrowed_ptr(py, root) }, 0));
+        while let Some(work) = stack.pop() {
+            let (node, depth) = match work {
+                Work::Node(node, depth) => (node, depth),
+                Work::Atom(text) => {
+                    document.push_text(&text);
+                    continue;
+                }
+                Work::Line => {
+                    document.push_line();
+                    continue;
+                }
+                Work::Close { open, start } => {
+                    document.push_text(")");
+                    document.tokens.push(Token::Close);
+                    document.tokens[open] = Token::Open {
+                        end: document.tokens.len() - 1,
+                        width: document.flat - start,
+                    };
+                    continue;
+                }
+            };
+            for finished in path.drain(depth..) {
+                on_path.remove(&finished.as_ptr());
+            }
+            if !on_path.insert(node.as_ptr()) {
+                return Err(cycle_error(py, path[depth - 1].as_ptr(), node.as_ptr()));
+            }
+            path.push(node.clone());
+            count += 1;
+            walker.check_budget(count)?;
+
+            let class = node.get_type();
+            for name in fields.names(&node)?.iter() {
+                let field = name.to_str()?;
+                let Some(value) = fields.get(&node, name)? else {
+                    continue;
+                };
+                if let Ok(list) = value.cast::<PyList>() {
+                    for item in list.iter() {
+                        if item.is_instance(&expr_context)? {
+                            continue;
+                        }
+                        children.push(if walker.is_ast(&item) {
+                            Work::Node(item, depth + 1)
+                        } else {
+                            Work::Atom(atom(&item, field)?)
+                        });
+                    }
+                } else if value.is_instance(&expr_context)? {
+                    continue;
+                } else if walker.is_ast(&value) {
+                    children.push(Work::Node(value, depth + 1));
+                } else if !(value.is_none()
+                    && class.getattr(name).is_ok_and(|default| default.is_none()))
+                {
+                    // Like `ast.dump`, leave out optional fields that are unset.
+                    children.push(Work::Atom(atom(&value, field)?));
+                }
+            }
+
+            let name = class.name()?;
+            if children.is_empty() {
+                document.push_text(name.to_str()?);
+                continue;
+            }
+            let open = document.tokens.len();
+            document.tokens.push(Token::Open { end: 0, width: 0 });
+            let start = document.flat;
+            document.push_text(&format!("({name}"));
+            stack.push(Work::Close { open, start });
+            for child in children.drain(..).rev() {
+                stack.push(child);
+                stack.push(Work::Line);
+            }
+        }
+
+        if i > 0 {
+            out.push('\n');
+        }
+        document.layout(width, &mut out);
+        document.clear();
+    }
+    Ok(out)
+}
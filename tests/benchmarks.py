@@ -2,10 +2,23 @@ from ast import AST, NodeVisitor as ASTNodeVisitor, parse
 from ast import walk as ast_walk
 import ast
 from collections.abc import Callable
+import difflib
 from pathlib import Path
+import pickle
 
 from pytest_codspeed import BenchmarkFixture
-from fast_walk import walk_dfs, walk_unordered, _walk_count
+from fast_walk import (
+    ast_equal,
+    dump,
+    dumps_binary,
+    loads_binary,
+    walk_dfs,
+    walk_grouped,
+    walk_many,
+    walk_unique,
+    walk_unordered,
+    _walk_count,
+)
 import pytest
 
 
@@ -37,6 +50,7 @@ def python_walk(node: AST) -> list[AST]:
         ast_walk_list,
         walk_dfs,
         walk_unordered,
+        walk_unique,
         python_walk,
     ],
 )
@@ -69,6 +83,136 @@ def test_walk_count(benchmark: BenchmarkFixture):
     benchmark(run)
 
 
+def _largest_stdlib_modules(count: int = 60) -> list[AST]:
+    stdlib = Path(ast.__file__).parent
+    paths = sorted(stdlib.glob("*.py"), key=lambda p: p.stat().st_size, reverse=True)
+    return [
+        parse(path.read_text(encoding="utf-8", errors="replace"))
+        for path in paths[:count]
+    ]
+
+
+@pytest.mark.parametrize("algorithm", [walk_dfs, walk_unordered])
+def test_walk_large(benchmark: BenchmarkFixture, algorithm: Callable[[AST], list[AST]]):
+    """~400k nodes across the 60 largest stdlib modules — far more node
+    headers and dict keys than fit in cache, which is where the
+    prefetching in `walk_unordered` has to earn its keep.
+
+    Best-of-N on a 1-vCPU x86_64 VM, CPython 3.13 (noisy; a few %):
+
+    | loop                              | difflib | this tree |
+    | --------------------------------- | ------- | --------- |
+    | walk_dfs                          | ~130 µs | ~35 ms    |
+    | pipelined header + ma_keys        | ~150 µs | ~34 ms    |
+
+    The pipeline only wins once the tree outgrows the cache: on difflib
+    alone the prefetches are overhead. Prefetching each list field's
+    `ob_item` array as well (deferring the list's expansion until the
+    prefetch landed) measured no better than this and was dropped.
+    """
+    roots = _largest_stdlib_modules()
+
+    def run():
+        algorithm(roots)
+
+    benchmark(run)
+
+
+def walk_each_unordered(roots: list[AST]) -> list[list[AST]]:
+    return [walk_unordered(root) for root in roots]
+
+
+def walk_many_per_root(roots: list[AST]) -> list[list[AST]]:
+    return walk_many(roots, flatten=False)
+
+
+@pytest.mark.parametrize("algorithm", [walk_each_unordered, walk_many_per_root])
+def test_walk_statements(
+    benchmark: BenchmarkFixture, algorithm: Callable[[list[AST]], list[list[AST]]]
+):
+    """Re-walking every top-level statement separately, as an incremental
+    analyzer does after an edit. Measures what `walk_many` saves over a
+    Python loop of single walks."""
+    import difflib
+
+    source_code = Path(difflib.__file__).read_text()
+    roots = parse(source_code).body
+
+    def run():
+        algorithm(roots)
+
+    benchmark(run)
+
+
+def group_in_python(node: AST) -> dict[type[AST], list[AST]]:
+    groups: dict[type[AST], list[AST]] = {}
+    for n in walk_unordered(node):
+        groups.setdefault(type(n), []).append(n)
+    return groups
+
+
+@pytest.mark.parametrize("algorithm", [group_in_python, walk_grouped])
+def test_walk_grouped(
+    benchmark: BenchmarkFixture, algorithm: Callable[[AST], dict[type[AST], list[AST]]]
+):
+    """Per-type node lists, as a lint pass that only cares about a few
+    node types wants them."""
+    import difflib
+
+    source_code = Path(difflib.__file__).read_text()
+    node = parse(source_code)
+
+    def run():
+        algorithm(node)
+
+    benchmark(run)
+
+
+@pytest.mark.parametrize("algorithm", [ast.dump, dump])
+def test_dump(benchmark: BenchmarkFixture, algorithm: Callable[..., str]):
+    """Snapshot-test style dump of a large module."""
+    node = parse(Path(difflib.__file__).read_text())
+
+    def run():
+        algorithm(node, indent=2)
+
+    benchmark(run)
+
+
+def dump_equal(a: AST, b: AST) -> bool:
+    """The usual pre-3.14 structural equality check."""
+    return ast.dump(a) == ast.dump(b)
+
+
+@pytest.mark.parametrize("algorithm", [dump_equal, ast_equal])
+def test_ast_equal(benchmark: BenchmarkFixture, algorithm: Callable[[AST, AST], bool]):
+    """Comparing two equal copies of a large module."""
+    source = Path(difflib.__file__).read_text()
+    a, b = parse(source), parse(source)
+
+    def run():
+        algorithm(a, b)
+
+    benchmark(run)
+
+
+@pytest.mark.parametrize(
+    ("dumps", "loads"),
+    [(pickle.dumps, pickle.loads), (dumps_binary, loads_binary)],
+    ids=["pickle", "binary"],
+)
+def test_cache_load(
+    benchmark: BenchmarkFixture, dumps: Callable[[AST], bytes], loads: Callable[[bytes], AST]
+):
+    """Loading a cached tree, as an incremental linter does on startup."""
+    data = dumps(parse(Path(difflib.__file__).read_text()))
+
+    def run():
+        loads(data)
+
+    benchmark(run)
+
+
 class NoStrRuleVisitor(ASTNodeVisitor):
     def __init__(self):
         self.violations: list[str] = []
@@ -0,0 +1,231 @@
+"""`to_arrow` (built with the `arrow` cargo feature).
+
+pyarrow is not a test dependency, so the exported C structs are read
+back with ctypes, following the Arrow C Data Interface layout.
+"""
+
+from __future__ import annotations
+
+import ast
+import ctypes
+import gc
+import textwrap
+
+import pytest
+
+import fast_walk
+
+pytestmark = pytest.mark.skipif(
+    not hasattr(fast_walk, "to_arrow"), reason="built without the arrow feature"
+)
+
+
+class ArrowSchema(ctypes.Structure):
+    pass
+
+
+ArrowSchema._fields_ = [
+    ("format", ctypes.c_char_p),
+    ("name", ctypes.c_char_p),
+    ("metadata", ctypes.c_char_p),
+    ("flags", ctypes.c_int64),
+    ("n_children", ctypes.c_int64),
+    ("children", ctypes.POINTER(ctypes.POINTER(ArrowSchema))),
+    ("dictionary", ctypes.POINTER(ArrowSchema)),
+    ("release", ctypes.c_void_p),
+    ("private_data", ctypes.c_void_p),
+]
+
+
+class ArrowArray(ctypes.Structure):
+    pass
+
+
+ArrowArray._fields_ = [
+    ("length", ctypes.c_int64),
+    ("null_count", ctypes.c_int64),
+    ("offset", ctypes.c_int64),
+    ("n_buffers", ctypes.c_int64),
+    ("n_children", ctypes.c_int64),
+    ("buffers", ctypes.POINTER(ctypes.c_void_p)),
+    ("children", ctypes.POINTER(ctypes.POINTER(ArrowArray))),
+    ("dictionary", ctypes.POINTER(ArrowArray)),
+    ("release", ctypes.c_void_p),
+    ("private_data", ctypes.c_void_p),
+]
+
+_capsule_pointer = ctypes.pythonapi.PyCapsule_GetPointer
+_capsule_pointer.restype = ctypes.c_void_p
+_capsule_pointer.argtypes = [ctypes.py_object, ctypes.c_char_p]
+
+_ARROW_FLAG_NULLABLE = 2
+
+
+def _read_column(schema: ArrowSchema, array: ArrowArray) -> list:
+    length, buffers = array.length, array.buffers
+    if buffers[0]:
+        bitmap = ctypes.string_at(buffers[0], (length + 7) // 8)
+        valid = [bool(bitmap[i // 8] >> (i % 8) & 1) for i in range(length)]
+    else:
+        valid = [True] * length
+    assert array.null_count == valid.count(False)
+    if schema.format == b"U":
+        offsets = (ctypes.c_int64 * (length + 1)).from_address(buffers[1])
+        data = ctypes.string_at(buffers[2], offsets[length]) if length else b""
+        values = [data[offsets[i] : offsets[i + 1]].decode() for i in range(length)]
+    else:
+        item = {b"l": ctypes.c_int64, b"i": ctypes.c_int32}[schema.format]
+        values = list((item * length).from_address(buffers[1])) if length else []
+    return [v if ok else None for v, ok in zip(values, valid)]
+
+
+def _read_table(table) -> dict[str, list]:
+    """Decode `table.__arrow_c_array__()` into `{column: values}`."""
+    schema_capsule, array_capsule = table.__arrow_c_array__()
+    schema = ArrowSchema.from_address(_capsule_pointer(schema_capsule, b"arrow_schema"))
+    array = ArrowArray.from_address(_capsule_pointer(array_capsule, b"arrow_array"))
+    assert schema.format == b"+s" and schema.n_children == array.n_children
+    assert array.length == len(table)
+    columns = {}
+    for i in range(schema.n_children):
+        child_schema, child_array = schema.children[i].contents, array.children[i].contents
+        assert child_schema.flags == _ARROW_FLAG_NULLABLE
+        assert child_array.length == array.length
+        columns[child_schema.name.decode()] = _read_column(child_schema, child_array)
+    return columns
+
+
+def _reference_rows(root: ast.AST) -> list[dict]:
+    """What each row should hold, built with the `ast` module."""
+    rows: list[dict] = []
+    todo = [(root, None, None, None)]
+    while todo:
+        node, parent, field, index = todo.pop()
+        node_id = len(rows)
+        rows.append(
+            {
+                "node_id": node_id,
+                "parent_id": parent,
+                "type": type(node).__name__,
+                "field": field,
+                "index": index,
+                "lineno": getattr(node, "lineno", None),
+                "col_offset": getattr(node, "col_offset", None),
+                "end_lineno": getattr(node, "end_lineno", None),
+                "end_col_offset": getattr(node, "end_col_offset", None),
+                "value": repr(node.value) if isinstance(node, ast.Constant) else None,
+            }
+        )
+        children = []
+        for name, value in ast.iter_fields(node):
+            if isinstance(value, list):
+                children.extend(
+                    (item, node_id, name, i)
+                    for i, item in enumerate(value)
+                    if isinstance(item, ast.AST)
+                )
+            elif isinstance(value, ast.AST):
+                children.append((value, node_id, name, None))
+        todo.extend(reversed(children))
+    return rows
+
+
+SOURCE = textwrap.dedent(
+    """
+    import os
+    def f(a, *args, b=1, **kw):
+        '''doc "quoted" \\u00e9'''
+        return {a: [b, ..., 2j, b"\\x00\\xff", None, True]}
+    x = {**kw, "k": f(1)[0]}
+    """
+)
+
+
+def test_to_arrow_matches_ast_fields():
+    tree = ast.parse(SOURCE)
+    columns = _read_table(fast_walk.to_arrow(tree))
+    rows = _reference_rows(tree)
+    assert list(columns) == list(rows[0])
+    for name, values in columns.items():
+        assert values == [row[name] for row in rows], name
+
+
+def test_to_arrow_uses_fields_order_not_storage_order():
+    """Keyword-constructed nodes store their fields in argument order;
+    the export still follows `_fields`, like `ast.iter_fields`."""
+    node = ast.BinOp(right=ast.Constant(2), op=ast.Add(), left=ast.Constant(1))
+    columns = _read_table(fast_walk.to_arrow(node))
+    assert columns["field"] == [None, "left", "op", "right"]
+    assert columns["value"] == [None, "1", None, "2"]
+
+
+def test_to_arrow_roots_and_options():
+    body = ast.parse("x = 1\ny = 2").body
+    columns = _read_table(fast_walk.to_arrow(body))
+    assert columns["parent_id"].count(None) == 2
+    assert _read_table(fast_walk.to_arrow([]))["node_id"] == []
+    with pytest.raises(TypeError, match="to_arrow"):
+        fast_walk.to_arrow("x = 1")
+    columns = _read_table(fast_walk.to_arrow([body[0], "oops"], strict=False))
+    assert columns["type"][-1] == "str" and columns["lineno"][-1] is None
+    with pytest.raises(fast_walk.TreeTooLarge):
+        fast_walk.to_arrow(body, max_nodes=3)
+
+
+def test_to_arrow_exports_are_independent():
+    """Each export owns a reference to the column data, so it outlives
+    both the table object and other exports."""
+    table = fast_walk.to_arrow(ast.parse(SOURCE))
+    expected = _read_table(table)
+    schema_capsule, array_capsule = table.__arrow_c_array__(requested_schema=None)
+    schema = table.__arrow_c_schema__()
+    del table
+    gc.collect()
+    array = ArrowArray.from_address(_capsule_pointer(array_capsule, b"arrow_array"))
+    types = ArrowSchema.from_address(_capsule_pointer(schema, b"arrow_schema")).children[2]
+    assert _read_column(types.contents, array.children[2].contents) == expected["type"]
+    del schema_capsule, array_capsule, schema
+
+
+class ArrowArrayStream(ctypes.Structure):
+    pass
+
+
+_GET_SCHEMA = ctypes.CFUNCTYPE(
+    ctypes.c_int, ctypes.POINTER(ArrowArrayStream), ctypes.POINTER(ArrowSchema)
+)
+_GET_NEXT = ctypes.CFUNCTYPE(
+    ctypes.c_int, ctypes.POINTER(ArrowArrayStream), ctypes.POINTER(ArrowArray)
+)
+ArrowArrayStream._fields_ = [
+    ("get_schema", _GET_SCHEMA),
+    ("get_next", _GET_NEXT),
+    ("get_last_error", ctypes.c_void_p),
+    ("release", ctypes.c_void_p),
+    ("private_data", ctypes.c_void_p),
+]
+
+
+def test_to_arrow_stream_yields_one_batch():
+    tree = ast.parse(SOURCE)
+    table = fast_walk.to_arrow(tree)
+    expected = _read_table(table)
+    capsule = table.__arrow_c_stream__()
+    del table
+    stream = ArrowArrayStream.from_address(_capsule_pointer(capsule, b"arrow_array_stream"))
+    schema, batch, end = ArrowSchema(), ArrowArray(), ArrowArray()
+    assert stream.get_schema(ctypes.byref(stream), ctypes.byref(schema)) == 0
+    assert stream.get_next(ctypes.byref(stream), ctypes.byref(batch)) == 0
+    assert stream.get_next(ctypes.byref(stream), ctypes.byref(end)) == 0
+    assert not end.release
+    assert batch.length == len(expected["node_id"])
+    names = [schema.children[i].contents.name.decode() for i in range(schema.n_children)]
+    assert names == list(expected)
+    assert _read_column(schema.children[3].contents, batch.children[3].contents) == (
+        expected["field"]
+    )
+    # The consumer owns what get_schema/get_next produced.
+    for struct in (schema, batch):
+        ctypes.CFUNCTYPE(None, ctypes.c_void_p)(struct.release)(ctypes.addressof(struct))
+        assert not struct.release
+    del capsule
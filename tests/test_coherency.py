@@ -10,13 +10,22 @@ regardless of order.
 from __future__ import annotations
 
 import ast
+import base64
+import gc
+import itertools
+import json
+import math
+import pickle
+import re
+import sys
 import textwrap
 import warnings
+import weakref
 from collections import Counter
 
 import pytest
 
-from fast_walk import walk_dfs, walk_unordered
+from fast_walk import walk_dfs, walk_unique, walk_unordered
 import fast_walk
 
 
@@ -319,3 +328,1705 @@ def test_parent_back_references_on_real_file():
     assert _multiset(walk_unordered(tree)) == expected
 
 
+
+
+@pytest.mark.parametrize("walk_fn", [walk_dfs, walk_unordered])
+def test_subclass_defined_after_first_walk(walk_fn):
+    """The per-thread `_fields` table is snapshotted on the first walk.
+    AST classes created afterwards (late imports, runtime-generated DSL
+    nodes) must still have their children visited, including subclasses
+    more than two MRO hops below `ast.AST`."""
+    walk_fn(ast.parse("x = 1"))  # make sure the table is already built
+
+    class LateNode(ast.AST):
+        _fields = ("left", "right")
+
+    class LateName(ast.Name):
+        pass
+
+    class DeepLateName(LateName):
+        pass
+
+    leaf = DeepLateName(id="a", ctx=ast.Load())
+    late = LateNode(left=leaf, right=[ast.Constant(1), LateName(id="b", ctx=ast.Load())])
+    tree = ast.Module(body=[ast.Expr(value=late)], type_ignores=[])
+
+    result = walk_fn(tree)
+    assert _multiset(result) == _multiset(ast.walk(tree))
+    assert any(n is leaf for n in result)
+
+
+def test_late_subclass_as_root():
+    """A late subclass passed directly as the root is classified on the
+    spot rather than being treated as a leaf."""
+    walk_dfs(ast.parse("x = 1"))
+
+    class LateRoot(ast.AST):
+        _fields = ("child",)
+
+    child = ast.Name(id="x", ctx=ast.Load())
+    root = LateRoot(child=child)
+    assert _multiset(walk_dfs(root)) == _multiset(ast.walk(root))
+    assert _multiset(walk_unordered(root)) == _multiset(ast.walk(root))
+
+
+def test_hundreds_of_custom_subclasses():
+    """Projects like DSL compilers define hundreds of `ast.AST` subclasses.
+    The type table must grow rather than fill up and spin forever."""
+    classes = [
+        type(f"Custom{i}", (ast.AST,), {"_fields": ("child",)}) for i in range(600)
+    ]
+    node: ast.AST = ast.Name(id="leaf", ctx=ast.Load())
+    for cls in classes:
+        node = cls(child=node)
+
+    expected = _multiset(ast.walk(node))
+    assert _multiset(walk_dfs(node)) == expected
+    assert _multiset(walk_unordered(node)) == expected
+
+
+def test_recycled_type_addresses_are_not_misclassified():
+    """Classes created and dropped in a loop tend to reuse the same
+    memory. A new class must never inherit the `_fields` length cached
+    for a dead one that happened to live at the same address."""
+    for i in range(200):
+        fields = ("a", "b", "c")[: i % 3 + 1]
+        cls = type("Recycled", (ast.AST,), {"_fields": fields})
+        node = cls(**{f: ast.Constant(j) for j, f in enumerate(fields)})
+        assert _multiset(walk_dfs(node)) == _multiset(ast.walk(node))
+        del cls, node
+        gc.collect()
+
+
+def test_late_types_are_released():
+    """The type table pins the classes it learns after the first walk,
+    but only up to a cap: past 1024 of them it drops them all, so a
+    program that keeps creating AST classes doesn't leak every one.
+    Classes still in use are classified again on their next walk."""
+    kept = type("Kept", (ast.AST,), {"_fields": ("child",)})
+    dropped = type("Dropped", (ast.AST,), {"_fields": ("child",)})
+    walk_dfs(kept(child=ast.Pass()))
+    walk_dfs(dropped(child=ast.Pass()))
+    dropped_ref = weakref.ref(dropped)
+    del dropped
+
+    for i in range(1030):
+        walk_dfs(type(f"Churn{i}", (ast.AST,), {"_fields": ()})())
+    gc.collect()
+    assert dropped_ref() is None
+
+    node = kept(child=ast.Expr(ast.Name(id="x", ctx=ast.Load())))
+    assert _multiset(walk_dfs(node)) == _multiset(ast.walk(node))
+    assert _multiset(walk_unordered(node)) == _multiset(ast.walk(node))
+
+
+class _GenericNode(ast.AST):
+    """One class, many shapes: each instance carries its own `_fields`."""
+
+    _fields = ()
+    _fast_walk_dynamic_fields = True
+
+
+def _generic(fields: dict[str, object]) -> _GenericNode:
+    node = _GenericNode()
+    node._fields = tuple(fields)
+    for name, value in fields.items():
+        setattr(node, name, value)
+    return node
+
+
+@pytest.mark.parametrize("walk_fn", [walk_dfs, walk_unordered])
+def test_dynamic_fields_read_from_instance(walk_fn):
+    """Types flagged `_fast_walk_dynamic_fields` are walked through each
+    instance's own `_fields`, matching `ast.iter_child_nodes`."""
+    leaf = ast.Name(id="x", ctx=ast.Load())
+    tree = _generic(
+        {
+            "op": "add",
+            "args": [_generic({"value": leaf}), ast.Constant(1), "not a node"],
+            "target": _generic({}),
+        }
+    )
+    assert _multiset(walk_fn(tree)) == _multiset(ast.walk(tree))
+
+
+def test_dynamic_fields_dfs_order_matches_ast_walk_children():
+    """Children of a dynamic node are visited in `_fields` order."""
+    a, b, c = (ast.Name(id=n, ctx=ast.Load()) for n in "abc")
+    tree = _generic({"first": a, "rest": [b, c]})
+    assert walk_dfs(tree)[:4] == [tree, a, a.ctx, b]
+
+
+@pytest.mark.parametrize("walk_fn", [walk_dfs, walk_unordered])
+def test_dynamic_fields_longer_than_type_fields(walk_fn):
+    """An instance may list more fields than its class declares; the
+    extra ones must be visited too."""
+
+    class Pair(ast.AST):
+        _fields = ("left",)
+        _fast_walk_dynamic_fields = True
+
+    node = Pair(left=ast.Constant(1))
+    node._fields = ("left", "right")
+    node.right = ast.Constant(2)
+    assert _multiset(walk_fn(node)) == _multiset(ast.walk(node))
+
+
+@pytest.mark.parametrize("walk_fn", [walk_dfs, walk_unordered])
+def test_dynamic_fields_skip_missing_attributes(walk_fn):
+    """`ast.iter_fields` silently skips fields that aren't set."""
+    node = _generic({"present": ast.Constant(1)})
+    node._fields = ("missing", "present")
+    assert _multiset(walk_fn(node)) == _multiset(ast.walk(node))
+
+
+@pytest.mark.parametrize("walk_fn", [walk_dfs, walk_unordered])
+def test_dynamic_fields_computed_children_stay_alive(walk_fn):
+    """Children produced by a property exist only as long as someone
+    holds them; the result list must own them."""
+
+    class Computed(ast.AST):
+        _fields = ("child",)
+        _fast_walk_dynamic_fields = True
+
+        @property
+        def child(self):
+            return ast.Call(func=ast.Name(id="f", ctx=ast.Load()), args=[], keywords=[])
+
+    result = walk_fn(Computed())
+    gc.collect()
+    assert sorted(type(n).__name__ for n in result) == [
+        "Call",
+        "Computed",
+        "Load",
+        "Name",
+    ]
+
+
+@pytest.mark.parametrize("walk_fn", [walk_dfs, walk_unordered])
+def test_dynamic_fields_reentrant_walks(walk_fn):
+    """Python code run mid-walk — a `_fields` property, a `__del__` — may
+    itself call back into fast_walk."""
+    inner = ast.parse("f(x)")
+    expected = len(list(ast.walk(inner)))
+    seen = []
+
+    class Dropped(ast.AST):
+        def __del__(self):
+            seen.append(len(walk_unordered(inner)))
+
+    class Reentrant(ast.AST):
+        _fields = ("child",)
+        _fast_walk_dynamic_fields = True
+
+        @property
+        def child(self):
+            Dropped()  # freed right away, running `__del__` mid-walk
+            return ast.Constant(1)
+
+    def fields(node):
+        seen.append(len(walk_dfs(inner)))
+        return ("child",)
+
+    node = Reentrant()
+    # Only now: `ast.AST.__init__` needs the plain tuple.
+    Reentrant._fields = property(fields)
+    assert sorted(type(n).__name__ for n in walk_fn(node)) == ["Constant", "Reentrant"]
+    assert seen == [expected, expected]
+
+
+@pytest.mark.parametrize(
+    "walk_fn",
+    [
+        walk_dfs,
+        lambda node: walk_dfs(node, detect_cycles=True),
+        walk_unique,
+        walk_unordered,
+    ],
+)
+def test_dynamic_fields_property_clears_sibling_list(walk_fn):
+    """A `_fields` property that empties the list holding its node frees
+    the siblings still waiting on the walk's stack; the walk keeps them
+    alive until it returns."""
+
+    class Clearing(ast.AST):
+        _fields = ()
+        _fast_walk_dynamic_fields = True
+
+    def fields(node):
+        body.clear()
+        gc.collect()
+        return ()
+
+    def siblings():
+        return [ast.Expr(ast.Constant(i)) for i in range(8)]
+
+    first, second = Clearing(), Clearing()
+    body = [first, *siblings()]
+    tree = ast.Module(body=body, type_ignores=[])
+    # Only now: `ast.AST.__init__` needs the plain tuple.
+    Clearing._fields = property(fields)
+    try:
+        walked = walk_fn(tree)
+        values = sorted(n.value for n in walked if isinstance(n, ast.Constant))
+        assert values == list(range(8))
+        assert len(walked) == 1 + 1 + 8 * 2
+        assert body == []
+
+        body[:] = [second, *siblings()]
+        encoded = fast_walk.encode_tree(tree)
+        assert len(encoded.parent) == 1 + 1 + 8 * 2
+    finally:
+        Clearing._fields = ()
+
+
+class _NodeList(list):
+    pass
+
+
+def _tuple_tree() -> tuple[ast.AST, list[ast.AST]]:
+    """A tree whose children partly live in a tuple and a list subclass,
+    plus the nodes only reachable through those containers."""
+    hidden = [ast.Name(id="t", ctx=ast.Load()), ast.Constant(2), ast.Constant(3)]
+    call = ast.Call(
+        func=ast.Name(id="f", ctx=ast.Load()),
+        args=(hidden[0], "not a node", None),
+        keywords=_NodeList([ast.keyword(arg="k", value=hidden[1])]),
+    )
+    tree = ast.Module(
+        body=[ast.Expr(value=call), ast.Expr(value=ast.Tuple(elts=(hidden[2],), ctx=ast.Load()))],
+        type_ignores=[],
+    )
+    return tree, hidden
+
+
+def _walk_with_sequences(node: ast.AST) -> list[ast.AST]:
+    """Reference: `ast.walk`, but also looking inside tuples and list
+    subclasses."""
+    out, todo = [], [node]
+    while todo:
+        n = todo.pop()
+        out.append(n)
+        for name in n._fields:
+            value = getattr(n, name, None)
+            items = value if isinstance(value, (list, tuple)) else [value]
+            todo.extend(i for i in items if isinstance(i, ast.AST))
+    return out
+
+
+@pytest.mark.parametrize("walk_fn", [walk_dfs, walk_unordered])
+def test_sequences_off_by_default(walk_fn):
+    """Without the option, tuple-valued fields are opaque, exactly like
+    `ast.walk`."""
+    a = ast.Constant(1)
+    call = ast.Call(func=ast.Name(id="f", ctx=ast.Load()), args=(a,), keywords=[])
+    result = walk_fn(call)
+    assert _multiset(result) == _multiset(ast.walk(call))
+    assert all(n is not a for n in result)
+
+
+@pytest.mark.parametrize("walk_fn", [walk_dfs, walk_unordered])
+def test_sequences_descends_into_tuples_and_list_subclasses(walk_fn):
+    tree, hidden = _tuple_tree()
+    result = walk_fn(tree, sequences=True)
+    assert _multiset(result) == _multiset(_walk_with_sequences(tree))
+    assert {id(n) for n in hidden} <= {id(n) for n in result}
+
+
+def test_sequences_preserve_field_order_in_dfs():
+    a, b = ast.Constant(1), ast.Constant(2)
+    call = ast.Call(func=ast.Name(id="f", ctx=ast.Load()), args=(a, b), keywords=[])
+    result = walk_dfs(call, sequences=True)
+    assert [n for n in result if isinstance(n, ast.Constant)] == [a, b]
+
+
+def test_sequences_on_parsed_source_is_a_no_op(tree: ast.AST):
+    """Parsed trees only ever use plain lists, so the option must not
+    change anything for them."""
+    assert walk_dfs(tree, sequences=True) == walk_dfs(tree)
+
+
+@pytest.mark.parametrize("walk_fn", [walk_dfs, walk_unordered])
+def test_detect_cycles_is_transparent_on_valid_trees(walk_fn, tree: ast.AST):
+    """Shared singletons like `ast.Load()` appear under many parents but
+    are not cycles; the checked walk must return the plain DFS result."""
+    assert walk_fn(tree, detect_cycles=True) == walk_dfs(tree)
+
+
+@pytest.mark.parametrize("walk_fn", [walk_dfs, walk_unordered])
+def test_detect_cycles_allows_shared_subtrees(walk_fn):
+    """A DAG (one subtree under two parents) is malformed-ish but finite;
+    it must not be reported as a cycle."""
+    shared = ast.Name(id="x", ctx=ast.Load())
+    tree = ast.BinOp(left=shared, op=ast.Add(), right=shared)
+    assert _multiset(walk_fn(tree, detect_cycles=True)) == _multiset(ast.walk(tree))
+
+
+@pytest.mark.parametrize("walk_fn", [walk_dfs, walk_unordered])
+def test_detect_cycles_reports_ancestor_in_list_field(walk_fn):
+    tree = ast.parse("x = [1]")
+    assign = tree.body[0]
+    assign.value.elts.append(assign)
+    with pytest.raises(ValueError, match=r"Assign node .* List\.elts\[1\]"):
+        walk_fn(tree, detect_cycles=True)
+
+
+@pytest.mark.parametrize("walk_fn", [walk_dfs, walk_unordered])
+def test_detect_cycles_reports_self_reference_in_field(walk_fn):
+    expr = ast.Expr(value=ast.Constant(1))
+    expr.value = expr
+    with pytest.raises(ValueError, match=r"Expr node .* Expr\.value$"):
+        walk_fn(ast.Module(body=[expr], type_ignores=[]), detect_cycles=True)
+
+
+def test_walk_unique_is_dfs_without_repeats(tree: ast.AST):
+    """On a parsed tree, walk_unique is walk_dfs with every repeat of a
+    shared node (the `ast.Load()` singletons) dropped after its first
+    occurrence."""
+    result = walk_unique(tree)
+    assert result == list(dict.fromkeys(walk_dfs(tree)))
+    assert len({id(n) for n in result}) == len(result)
+
+
+def test_walk_unique_emits_shared_subtrees_once():
+    """Optimizer-style DAG: one subtree referenced from two parents."""
+    shared = ast.Call(func=ast.Name(id="f", ctx=ast.Load()), args=[], keywords=[])
+    tree = ast.BinOp(left=shared, op=ast.Add(), right=shared)
+    result = walk_unique(tree)
+    assert [id(n) for n in result].count(id(shared)) == 1
+    assert {id(n) for n in result} == {id(n) for n in ast.walk(tree)}
+
+
+def test_walk_unique_terminates_on_cycles():
+    tree = ast.parse("x = [1]")
+    assign = tree.body[0]
+    assign.value.elts.append(assign)
+    assert [type(n).__name__ for n in walk_unique(tree)] == [
+        "Module",
+        "Assign",
+        "Name",
+        "Store",
+        "List",
+        "Constant",
+        "Load",
+    ]
+
+
+def test_walk_unique_honors_sequences():
+    a = ast.Constant(1)
+    call = ast.Call(func=ast.Name(id="f", ctx=ast.Load()), args=(a, a), keywords=[])
+    assert a not in walk_unique(call)
+    assert walk_unique(call, sequences=True).count(a) == 1
+
+
+@pytest.mark.parametrize("walk_fn", [walk_dfs, walk_unordered, walk_unique])
+def test_max_nodes_at_exact_size_returns_everything(walk_fn, tree: ast.AST):
+    expected = walk_fn(tree)
+    assert walk_fn(tree, max_nodes=len(expected)) == expected
+
+
+@pytest.mark.parametrize("walk_fn", [walk_dfs, walk_unordered, walk_unique])
+def test_max_nodes_exceeded_raises_tree_too_large(walk_fn):
+    tree = ast.parse(SOURCES["many_small_statements"])
+    limit = len(walk_fn(tree)) - 1
+    with pytest.raises(fast_walk.TreeTooLarge) as excinfo:
+        walk_fn(tree, max_nodes=limit)
+    assert excinfo.value.limit == limit
+    assert excinfo.value.count == limit + 1
+    assert isinstance(excinfo.value, ValueError)
+
+
+def test_max_nodes_applies_to_checked_walk():
+    tree = ast.parse(SOURCES["functions"])
+    with pytest.raises(fast_walk.TreeTooLarge, match="max_nodes=3"):
+        walk_dfs(tree, detect_cycles=True, max_nodes=3)
+
+
+def test_max_nodes_zero_rejects_even_the_root():
+    with pytest.raises(fast_walk.TreeTooLarge) as excinfo:
+        walk_dfs(ast.Constant(1), max_nodes=0)
+    assert excinfo.value.count == 1
+
+
+def test_walk_unique_budget_counts_distinct_nodes():
+    """Repeats of shared singletons don't count against the budget."""
+    tree = ast.parse(SOURCES["comprehensions"])
+    distinct = len(walk_unique(tree))
+    assert distinct < len(walk_dfs(tree))
+    assert len(walk_unique(tree, max_nodes=distinct)) == distinct
+
+
+ALL_WALKS = [walk_dfs, walk_unordered, walk_unique]
+
+
+@pytest.mark.parametrize("walk_fn", ALL_WALKS)
+@pytest.mark.parametrize("bad_root", ["x = 1", 42, None, ("tuple",)])
+def test_non_ast_root_raises_type_error(walk_fn, bad_root):
+    with pytest.raises(TypeError, match=f"got {type(bad_root).__name__}$"):
+        walk_fn(bad_root)
+
+
+@pytest.mark.parametrize("walk_fn", ALL_WALKS)
+def test_non_strict_returns_non_ast_root_as_leaf(walk_fn):
+    obj = "x = 1"
+    assert walk_fn(obj, strict=False) == [obj]
+
+
+@pytest.mark.parametrize("walk_fn", ALL_WALKS)
+def test_bare_ast_instance_is_a_valid_root(walk_fn):
+    node = ast.AST()
+    assert walk_fn(node) == [node]
+
+
+def test_list_root_walks_each_item_in_order(tree: ast.AST):
+    """`walk_dfs(tree.body)` is the concatenation of the per-statement
+    walks — the list itself is not part of the result."""
+    body = tree.body
+    expected = [n for stmt in body for n in walk_dfs(stmt)]
+    assert walk_dfs(body) == expected
+    assert _multiset(walk_unordered(body)) == _multiset(expected)
+    assert walk_unique(body) == list(dict.fromkeys(expected))
+
+
+@pytest.mark.parametrize("walk_fn", ALL_WALKS)
+def test_empty_list_root(walk_fn):
+    assert walk_fn([]) == []
+
+
+@pytest.mark.parametrize("walk_fn", ALL_WALKS)
+def test_list_root_with_non_ast_item(walk_fn):
+    stmt = ast.parse("x = 1").body[0]
+    with pytest.raises(TypeError, match="item 1 is str"):
+        walk_fn([stmt, "oops"])
+    result = walk_fn([stmt, "oops"], strict=False)
+    assert _multiset(result) == _multiset([*ast.walk(stmt), "oops"])
+
+
+def test_walk_many_flat_matches_individual_walks(tree: ast.AST):
+    stmts = tree.body
+    expected = _multiset(n for stmt in stmts for n in ast.walk(stmt))
+    assert _multiset(fast_walk.walk_many(stmts)) == expected
+    assert fast_walk.walk_many(stmts, dfs=True) == walk_dfs(stmts)
+
+
+def test_walk_many_per_root_lists(tree: ast.AST):
+    stmts = tree.body
+    per_root = fast_walk.walk_many(stmts, flatten=False)
+    assert type(per_root) is list
+    assert len(per_root) == len(stmts)
+    for stmt, nodes in zip(stmts, per_root):
+        assert type(nodes) is list
+        assert _multiset(nodes) == _multiset(ast.walk(stmt))
+    assert fast_walk.walk_many(stmts, flatten=False, dfs=True) == [
+        walk_dfs(stmt) for stmt in stmts
+    ]
+
+
+def test_walk_many_accepts_any_iterable():
+    """A generator of freshly built nodes: walk_many must own the roots,
+    since nothing else references them."""
+    roots = (ast.parse(f"x{i} = {i}").body[0] for i in range(50))
+    result = fast_walk.walk_many(roots, flatten=False, dfs=True)
+    gc.collect()
+    assert [type(nodes[0]).__name__ for nodes in result] == ["Assign"] * 50
+    assert [nodes[1].id for nodes in result] == [f"x{i}" for i in range(50)]
+
+
+def test_walk_many_validates_items():
+    stmt = ast.parse("x = 1").body[0]
+    with pytest.raises(TypeError, match=r"walk_many\(\) expects ast.AST nodes, but item 1 is int"):
+        fast_walk.walk_many([stmt, 1])
+    assert fast_walk.walk_many([stmt, 1], flatten=False, strict=False)[1] == [1]
+    with pytest.raises(TypeError):
+        fast_walk.walk_many(stmt)  # a single node is not an iterable of roots
+
+
+def test_walk_many_budget_covers_the_whole_call():
+    stmts = ast.parse(SOURCES["many_small_statements"]).body
+    total = len(fast_walk.walk_many(stmts))
+    assert len(fast_walk.walk_many(stmts, flatten=False, max_nodes=total)) == len(stmts)
+    with pytest.raises(fast_walk.TreeTooLarge):
+        fast_walk.walk_many(stmts, flatten=False, max_nodes=total - 1)
+
+
+def test_walk_many_empty():
+    assert fast_walk.walk_many([]) == []
+    assert fast_walk.walk_many([], flatten=False) == []
+
+
+@pytest.mark.parametrize("walk_fn", ALL_WALKS)
+def test_into_refills_and_returns_the_same_list(walk_fn, tree: ast.AST):
+    out = ["stale", "entries"]
+    result = walk_fn(tree, into=out)
+    assert result is out
+    assert out == walk_fn(tree)
+
+
+def test_into_reused_across_trees_of_different_sizes():
+    """Refilling must handle both growing past and shrinking below the
+    list's current allocation."""
+    out = []
+    for name in ["many_small_statements", "single_stmt", "deeply_nested", "empty"]:
+        tree = ast.parse(SOURCES[name])
+        assert walk_dfs(tree, into=out) is out
+        assert out == walk_dfs(tree)
+    empty = walk_dfs([], into=out)
+    assert empty is out and out == []
+
+
+def test_into_may_alias_the_root_list():
+    tree = ast.parse(SOURCES["many_small_statements"])
+    stmts = list(tree.body)
+    expected = walk_dfs(stmts)
+    assert walk_dfs(stmts, into=stmts) is stmts
+    assert stmts == expected
+
+
+def test_into_left_untouched_when_the_walk_fails():
+    tree = ast.parse(SOURCES["many_small_statements"])
+    out = ["keep"]
+    with pytest.raises(fast_walk.TreeTooLarge):
+        walk_unordered(tree, max_nodes=3, into=out)
+    assert out == ["keep"]
+
+
+def test_into_displaced_entries_may_reenter():
+    """The references `into=` displaces are released while the walk still
+    holds this thread's scratch buffers; a `__del__` walking another tree
+    then gets buffers of its own."""
+    inner = ast.parse("f(x)")
+    seen = []
+
+    class Stale:
+        def __del__(self):
+            seen.append(_multiset(walk_dfs(inner)) == _multiset(ast.walk(inner)))
+
+    tree = ast.parse(SOURCES["many_small_statements"])
+    out = [Stale(), Stale()]
+    assert walk_dfs(tree, into=out) is out
+    assert out == walk_dfs(tree)
+    assert seen == [True, True]
+
+
+def test_into_must_be_a_list():
+    tree = ast.parse("x = 1")
+    with pytest.raises(TypeError):
+        walk_dfs(tree, into=())
+
+
+def test_walk_many_into():
+    stmts = ast.parse(SOURCES["many_small_statements"]).body
+    out = [None]
+    assert fast_walk.walk_many(stmts, dfs=True, into=out) is out
+    assert out == walk_dfs(stmts)
+    with pytest.raises(ValueError, match="flatten=True"):
+        fast_walk.walk_many(stmts, flatten=False, into=out)
+
+
+@pytest.mark.parametrize("walk_fn", ALL_WALKS)
+@pytest.mark.parametrize("size_hint", [0, 1, 10_000, 2**62])
+def test_size_hint_never_changes_the_result(walk_fn, size_hint, tree: ast.AST):
+    assert walk_fn(tree, size_hint=size_hint) == walk_fn(tree)
+
+
+def test_size_hint_after_walks_of_different_sizes():
+    """The adaptive estimate is only a starting capacity: a small walk
+    after a big one, and vice versa, must still return exact results."""
+    big = ast.parse(SOURCES["many_small_statements"])
+    small = ast.parse(SOURCES["single_stmt"])
+    for tree in [big, small, big, small]:
+        assert _multiset(walk_unordered(tree)) == _multiset(ast.walk(tree))
+    assert fast_walk.walk_many(big.body, size_hint=3) == walk_unordered(big.body)
+
+
+def test_size_hint_must_be_non_negative():
+    with pytest.raises(OverflowError):
+        walk_dfs(ast.parse("x = 1"), size_hint=-1)
+
+
+@pytest.mark.parametrize("batch", [1, 2, 3, 4, 8, 16, 32])
+def test_walk_unordered_batch_sizes_visit_the_same_nodes(batch, tree: ast.AST):
+    assert _multiset(walk_unordered(tree, batch=batch)) == _multiset(ast.walk(tree))
+
+
+def test_walk_unordered_batch_of_one_is_depth_first(tree: ast.AST):
+    assert walk_unordered(tree, batch=1) == walk_dfs(tree)
+
+
+@pytest.mark.parametrize("batch", [0, 33, 1000])
+def test_walk_unordered_rejects_out_of_range_batch(batch):
+    with pytest.raises(ValueError, match="batch must be between 1 and 32"):
+        walk_unordered(ast.parse("x = 1"), batch=batch)
+
+
+def test_calibrate_picks_a_candidate_and_sets_the_default():
+    tree = ast.parse(SOURCES["control_flow"])
+    try:
+        assert fast_walk.calibrate([3], rounds=1) == 3
+        assert walk_unordered(tree) == walk_unordered(tree, batch=3)
+        assert fast_walk.calibrate([2, 5], rounds=2) in (2, 5)
+        assert fast_walk.calibrate(rounds=1) in (1, 2, 4, 6, 8, 12, 16)
+    finally:
+        fast_walk.calibrate([4], rounds=1)
+    assert walk_unordered(tree) == walk_unordered(tree, batch=4)
+
+
+@pytest.mark.parametrize(
+    ("kwargs", "message"),
+    [
+        ({"candidates": []}, "at least one candidate"),
+        ({"candidates": [4, 0]}, "batch must be between 1 and 32"),
+        ({"rounds": 0}, "rounds must be at least 1"),
+    ],
+)
+def test_calibrate_validates_arguments(kwargs, message):
+    with pytest.raises(ValueError, match=message):
+        fast_walk.calibrate(**kwargs)
+
+
+@pytest.mark.parametrize("batch", [1, 4, 32])
+def test_walk_unordered_list_fields(batch):
+    """Whatever the prefetch distance, empty lists, lists of strings
+    (`Global.names`), nested list fields and list-valued roots must all
+    come out exactly as ast.walk has them."""
+    tree = ast.parse(
+        textwrap.dedent("""
+            global a, b
+            def f(): pass
+            [[], [[x]], {}, ()]
+            class C(A, B, metaclass=M): ...
+        """)
+    )
+    expected = _multiset(ast.walk(tree))
+    assert _multiset(walk_unordered(tree, batch=batch)) == expected
+    assert _multiset(walk_unordered(tree.body, batch=batch)) == expected - Counter({id(tree): 1})
+    for limit in (0, 5, len(expected) - 1):
+        with pytest.raises(fast_walk.TreeTooLarge) as excinfo:
+            walk_unordered(tree, batch=batch, max_nodes=limit)
+        assert excinfo.value.count == limit + 1
+
+
+def _grouped_by_type(nodes) -> dict[type, Counter[int]]:
+    groups: dict[type, Counter[int]] = {}
+    for n in nodes:
+        groups.setdefault(type(n), Counter())[id(n)] += 1
+    return groups
+
+
+def test_walk_grouped_matches_ast_walk_by_type(tree: ast.AST):
+    groups = fast_walk.walk_grouped(tree)
+    assert type(groups) is dict
+    assert all(type(nodes) is list for nodes in groups.values())
+    assert {t: _multiset(nodes) for t, nodes in groups.items()} == _grouped_by_type(
+        ast.walk(tree)
+    )
+
+
+def test_walk_grouped_follows_walk_unordered_order(tree: ast.AST):
+    """Keys come in order of first appearance and each bucket keeps the
+    traversal order, so flattening by position recovers the walk."""
+    flat = walk_unordered(tree)
+    groups = fast_walk.walk_grouped(tree)
+    assert list(groups) == list(dict.fromkeys(type(n) for n in flat))
+    for node_type, nodes in groups.items():
+        assert nodes == [n for n in flat if type(n) is node_type]
+
+
+def test_walk_grouped_uses_exact_types():
+    """Subclasses, late ones included, get their own bucket rather than
+    landing in their base's."""
+    fast_walk.walk_grouped(ast.parse("x = 1"))
+
+    class LateName(ast.Name):
+        pass
+
+    late = LateName(id="b", ctx=ast.Load())
+    tree = ast.Module(
+        body=[ast.Expr(value=ast.BinOp(ast.Name(id="a", ctx=ast.Load()), ast.Add(), late))],
+        type_ignores=[],
+    )
+    groups = fast_walk.walk_grouped(tree)
+    assert groups[LateName] == [late]
+    assert len(groups[ast.Name]) == 1
+
+
+def test_walk_grouped_dynamic_fields():
+    leaf = ast.Constant(1)
+    node = _generic({"a": leaf, "b": [_generic({"c": leaf})]})
+    groups = fast_walk.walk_grouped(node)
+    assert {t: _multiset(nodes) for t, nodes in groups.items()} == _grouped_by_type(
+        ast.walk(node)
+    )
+
+
+def test_walk_grouped_roots_and_options():
+    stmt = ast.parse("x = 1").body[0]
+    assert fast_walk.walk_grouped([]) == {}
+    with pytest.raises(TypeError, match="item 1 is str"):
+        fast_walk.walk_grouped([stmt, "oops"])
+    groups = fast_walk.walk_grouped([stmt, "oops", "again"], strict=False)
+    assert groups[str] == ["oops", "again"]
+    assert {t: _multiset(nodes) for t, nodes in groups.items() if t is not str} == (
+        _grouped_by_type(ast.walk(stmt))
+    )
+    with pytest.raises(fast_walk.TreeTooLarge):
+        fast_walk.walk_grouped(stmt, max_nodes=2)
+
+    node, hidden = _tuple_tree()
+    groups = fast_walk.walk_grouped(node, sequences=True)
+    assert {t: _multiset(nodes) for t, nodes in groups.items()} == _grouped_by_type(
+        _walk_with_sequences(node)
+    )
+    assert not any(n is hidden[0] for n in fast_walk.walk_grouped(node)[ast.Name])
+
+
+def _encode_reference(roots) -> list[tuple[ast.AST, int, int]]:
+    """(node, parent index, depth) rows in `walk_dfs` order."""
+    rows: list[tuple[ast.AST, int, int]] = []
+    todo = [(root, -1, 0) for root in reversed(roots)]
+    while todo:
+        node, parent, depth = todo.pop()
+        index = len(rows)
+        rows.append((node, parent, depth))
+        todo.extend((c, index, depth + 1) for c in reversed(list(ast.iter_child_nodes(node))))
+    return rows
+
+
+def _columns(encoded) -> dict[str, list[int]]:
+    names = ("type_code", "parent", "depth", "lineno", "col_offset")
+    return {name: memoryview(getattr(encoded, name)).tolist() for name in names}
+
+
+def test_encode_tree_matches_walk_dfs(tree: ast.AST):
+    encoded = fast_walk.encode_tree(tree)
+    columns = _columns(encoded)
+    rows = _encode_reference([tree])
+    vocabulary = fast_walk.type_vocabulary()
+    assert len(encoded) == len(rows) == len(walk_dfs(tree))
+    assert [vocabulary[c] for c in columns["type_code"]] == [type(n) for n, _, _ in rows]
+    assert columns["parent"] == [p for _, p, _ in rows]
+    assert columns["depth"] == [d for _, _, d in rows]
+    assert columns["lineno"] == [getattr(n, "lineno", -1) for n, _, _ in rows]
+    assert columns["col_offset"] == [getattr(n, "col_offset", -1) for n, _, _ in rows]
+
+
+def test_encode_tree_columns_are_read_only_int32_buffers():
+    encoded = fast_walk.encode_tree(ast.parse("x = 1"))
+    view = memoryview(encoded.depth)
+    assert (view.format, view.itemsize, view.ndim, view.readonly) == ("i", 4, 1, True)
+    assert view.shape == (len(encoded),) and len(encoded.depth) == len(encoded)
+    assert bytes(encoded.parent)[:4] == (-1).to_bytes(4, "little", signed=True)
+    with pytest.raises(TypeError):
+        view[0] = 1
+    empty = fast_walk.encode_tree([])
+    assert len(empty) == 0 and bytes(empty.type_code) == b""
+
+
+def test_encode_tree_views_keep_the_array_alive():
+    view = memoryview(fast_walk.encode_tree(ast.parse("x = y")).lineno)
+    gc.collect()
+    assert view.tolist() == [-1, 1, 1, -1, 1, -1]
+
+
+def test_type_vocabulary_is_stable():
+    """Stdlib classes only, `ast.AST` first, each once, and identical on
+    a fresh thread whose type table is built separately."""
+    import threading
+
+    vocabulary = fast_walk.type_vocabulary()
+    assert vocabulary[0] is ast.AST
+    assert len(set(vocabulary)) == len(vocabulary)
+    assert all(t.__module__ == "ast" for t in vocabulary)
+    assert ast.Name in vocabulary and ast.Module in vocabulary
+
+    class UserNode(ast.AST):
+        pass
+
+    other = []
+    thread = threading.Thread(target=lambda: other.append(fast_walk.type_vocabulary()))
+    thread.start()
+    thread.join()
+    assert other == [vocabulary]
+
+
+def test_encode_tree_codes_subclasses_as_their_stdlib_base():
+    class LateName(ast.Name):
+        pass
+
+    class LateNode(ast.AST):
+        _fields = ("child",)
+
+    vocabulary = fast_walk.type_vocabulary()
+    tree = LateNode(child=LateName(id="x", ctx=ast.Load(), lineno=3, col_offset=4))
+    columns = _columns(fast_walk.encode_tree(tree))
+    assert [vocabulary[c] for c in columns["type_code"]] == [ast.AST, ast.Name, ast.Load]
+    assert columns["lineno"] == [-1, 3, -1]
+    assert columns["col_offset"] == [-1, 4, -1]
+
+
+def test_encode_tree_roots_and_options():
+    module = ast.parse("x = 1\ny = 2")
+    columns = _columns(fast_walk.encode_tree(module.body))
+    assert columns["parent"] == [p for _, p, _ in _encode_reference(module.body)]
+    assert columns["parent"].count(-1) == 2
+    with pytest.raises(TypeError, match="encode_tree"):
+        fast_walk.encode_tree("x = 1")
+    columns = _columns(fast_walk.encode_tree([module.body[0], "oops"], strict=False))
+    assert columns["type_code"][-1] == columns["parent"][-1] == columns["lineno"][-1] == -1
+    with pytest.raises(fast_walk.TreeTooLarge):
+        fast_walk.encode_tree(module, max_nodes=3)
+
+    node, _ = _tuple_tree()
+    assert len(fast_walk.encode_tree(node, sequences=True)) == len(_walk_with_sequences(node))
+
+
+def test_encode_tree_ignores_non_int_positions():
+    node = ast.Name(id="x", ctx=ast.Load(), lineno=None, col_offset=2**40)
+    columns = _columns(fast_walk.encode_tree(node))
+    assert columns["lineno"][0] == columns["col_offset"][0] == -1
+
+
+# ---------------------------------------------------------------------------
+# to_json
+# ---------------------------------------------------------------------------
+
+
+def _json_value(value, include_positions: bool):
+    """The object `to_json` serializes `value` as, built in Python."""
+    if isinstance(value, ast.AST):
+        obj = {"_type": type(value).__name__}
+        names = list(value._fields)
+        if include_positions:
+            names += getattr(type(value), "_attributes", ())
+        for name in names:
+            if hasattr(value, name):
+                obj[name] = _json_value(getattr(value, name), include_positions)
+        return obj
+    if isinstance(value, list):
+        return [_json_value(item, include_positions) for item in value]
+    if value is None or isinstance(value, (bool, int, str)):
+        return value
+    if isinstance(value, float):
+        return value if math.isfinite(value) else {"_type": "float", "value": repr(value)}
+    if isinstance(value, bytes):
+        return {"_type": "bytes", "base64": base64.b64encode(value).decode()}
+    if isinstance(value, complex):
+        return {
+            "_type": "complex",
+            "real": _json_value(value.real, include_positions),
+            "imag": _json_value(value.imag, include_positions),
+        }
+    if value is ...:
+        return {"_type": "Ellipsis"}
+    if isinstance(value, (tuple, frozenset)):
+        elts = [_json_value(item, include_positions) for item in value]
+        return {"_type": type(value).__name__, "elts": elts}
+    raise TypeError(type(value).__name__)
+
+
+@pytest.mark.parametrize("include_positions", [True, False])
+@pytest.mark.parametrize("indent", [None, 0, 2])
+def test_to_json_matches_json_dumps(tree: ast.AST, include_positions: bool, indent):
+    expected = json.dumps(_json_value(tree, include_positions), indent=indent)
+    assert fast_walk.to_json(tree, include_positions, indent) == expected
+
+
+CONSTANTS = [
+    None,
+    True,
+    False,
+    0,
+    -(2**100),
+    1.5,
+    -0.0,
+    1e300,
+    float("inf"),
+    float("-inf"),
+    float("nan"),
+    "",
+    'quote " backslash \\ slash / controls \n\r\t\b\f\x00\x1f\x7f',
+    "é ☃ \U0001f600 \ud800 lone surrogate",
+    b"",
+    b"\x00\xff",
+    b"abcd",
+    b"abcde",
+    2j,
+    complex(1.5, float("nan")),
+    ...,
+    (1, ("a", b"b"), ()),
+    frozenset(),
+]
+
+
+@pytest.mark.parametrize("value", CONSTANTS, ids=repr)
+def test_to_json_constant_values(value):
+    node = ast.Constant(value=value, lineno=1, col_offset=0)
+    expected = json.dumps(_json_value(node, True))
+    assert fast_walk.to_json(node) == expected
+    # Whatever the value, the output is ASCII-only valid JSON.
+    assert fast_walk.to_json(node).isascii()
+    assert json.loads(expected) is not None
+
+
+def test_to_json_shape():
+    call = ast.parse("f(x, 1)", mode="eval").body
+    assert json.loads(fast_walk.to_json(call, include_positions=False)) == {
+        "_type": "Call",
+        "func": {"_type": "Name", "id": "f", "ctx": {"_type": "Load"}},
+        "args": [
+            {"_type": "Name", "id": "x", "ctx": {"_type": "Load"}},
+            {"_type": "Constant", "value": 1, "kind": None},
+        ],
+        "keywords": [],
+    }
+    assert json.loads(fast_walk.to_json(call))["end_col_offset"] == 7
+
+
+def test_to_json_field_order_and_missing_fields():
+    node = ast.BinOp(right=ast.Constant(2), op=ast.Add(), left=ast.Constant(1))
+    keys = list(json.loads(fast_walk.to_json(node, include_positions=False)))
+    assert keys == ["_type", "left", "op", "right"]
+    with warnings.catch_warnings():
+        warnings.simplefilter("ignore", DeprecationWarning)
+        partial = ast.Name()
+    del partial.ctx
+    assert json.loads(fast_walk.to_json(partial, False)) == {"_type": "Name"}
+
+
+def test_to_json_roots_and_errors():
+    body = ast.parse("x = 1\ny = 2").body
+    assert json.loads(fast_walk.to_json(body)) == [
+        json.loads(fast_walk.to_json(stmt)) for stmt in body
+    ]
+    assert fast_walk.to_json([]) == "[]"
+    with pytest.raises(TypeError, match="to_json"):
+        fast_walk.to_json("x = 1")
+    with pytest.raises(TypeError, match="to_json"):
+        fast_walk.to_json([body[0], "oops"])
+    with pytest.raises(TypeError, match="not JSON serializable"):
+        fast_walk.to_json(ast.Constant(value=object()))
+
+
+def test_to_json_deep_tree_raises_recursion_error():
+    node = ast.Constant(1)
+    for _ in range(100_000):
+        node = ast.UnaryOp(op=ast.USub(), operand=node)
+    with pytest.raises(RecursionError):
+        fast_walk.to_json(node)
+
+
+# ---------------------------------------------------------------------------
+# from_json
+# ---------------------------------------------------------------------------
+
+
+def test_from_json_round_trips(tree: ast.AST):
+    rebuilt = fast_walk.from_json(fast_walk.to_json(tree))
+    assert type(rebuilt) is type(tree)
+    assert ast.dump(rebuilt, include_attributes=True) == ast.dump(tree, include_attributes=True)
+    compile(rebuilt, "<from_json>", "exec")
+
+
+def test_from_json_round_trips_without_positions(tree: ast.AST):
+    rebuilt = fast_walk.from_json(fast_walk.to_json(tree, include_positions=False, indent=2))
+    assert ast.dump(rebuilt) == ast.dump(tree)
+    compile(ast.fix_missing_locations(rebuilt), "<from_json>", "exec")
+
+
+@pytest.mark.parametrize("value", CONSTANTS, ids=repr)
+def test_from_json_constant_values(value):
+    rebuilt = fast_walk.from_json(fast_walk.to_json(ast.Constant(value=value))).value
+    assert type(rebuilt) is type(value)
+    if isinstance(value, (float, complex)):
+        assert repr(rebuilt) == repr(value)
+    else:
+        assert rebuilt == value
+
+
+def test_from_json_reads_plain_json():
+    """Anything `json` writes for the same objects is accepted."""
+    obj = {
+        "_type": "Constant",
+        "value": "é \U0001f600 \ud800 \"\\/",
+        "kind": None,
+        "lineno": 2**70,
+        "col_offset": -0,
+    }
+    compact = json.dumps(obj, separators=(",", ":"))
+    for text in (json.dumps(obj), json.dumps(obj, indent="\t"), compact):
+        node = fast_walk.from_json(text)
+        assert (node.value, node.kind, node.lineno, node.col_offset) == (
+            obj["value"], None, 2**70, 0
+        )
+    assert fast_walk.from_json(' [ 1.5e3 , -2 , true , false , null ] ') == [
+        1500.0, -2, True, False, None
+    ]
+
+
+@pytest.mark.parametrize(
+    ("text", "message"),
+    [
+        (
+            '{"_type": "Module", "body": [{"_type": "Foo"}]}',
+            r'unknown "_type" "Foo" at \$\.body\[0\]$',
+        ),
+        (
+            '{"_type": "Expr", "value": {"_type": "Name", "idx": 1}}',
+            r'Name has no field "idx" at \$\.value\.idx$',
+        ),
+        ('[{"_type": "Load", "lineno": 1}]', r'Load has no field "lineno" at \$\[0\]\.lineno$'),
+        ('{"_type": "Pass", "a b": {}}', r'object has no "_type" at \$\["a b"\]$'),
+        ('{"_type": 1}', r'"_type" is not a string at \$$'),
+        ('{"_type": "AST_"}', r'unknown "_type" "AST_" at \$$'),
+        ('{"_type": "bytes", "base64": "abc"}', r"not valid base64 at \$$"),
+        ('{"_type": "bytes"}', r'bytes object has no "base64" at \$$'),
+        ('{"_type": "float", "value": "Infinity"}', r'"inf", "-inf" or "nan" at \$$'),
+        ('{"_type": "tuple", "elts": [], "x": 1}', r"unexpected key in tuple object at \$\.x$"),
+        (
+            '{"_type": "Expr", "value": [1, 2}',
+            r"expected ',' or '\]', found '}' at \$\.value \(line 1, column 33\)$",
+        ),
+        ('{"_type": "Pass"} x', r"expected end of input, found 'x' at \$ \(line 1, column 19\)"),
+        ('[\n  01]', r"expected ',' or '\]', found '1' at \$ \(line 2, column 4\)"),
+        ('"\\x"', r"expected an escape sequence"),
+        ('"\\u12"', r"expected four hex digits"),
+        ('"a\nb"', r"""expected '"'"""),
+        ("", r"expected a value, found end of input"),
+        ("nul", r"expected 'null'"),
+    ],
+)
+def test_from_json_errors_report_the_path(text: str, message: str):
+    with pytest.raises(ValueError, match=message):
+        fast_walk.from_json(text)
+
+
+def test_from_json_only_builds_stdlib_classes():
+    class Custom(ast.expr):
+        _fields = ()
+
+    with pytest.raises(ValueError, match='unknown "_type" "Custom"'):
+        fast_walk.from_json('{"_type": "Custom"}')
+    assert type(fast_walk.from_json('{"_type": "expr"}')) is ast.expr
+
+
+def test_from_json_deep_document_raises_recursion_error():
+    with pytest.raises(RecursionError):
+        fast_walk.from_json("[" * 100_000 + "]" * 100_000)
+
+
+# ---------------------------------------------------------------------------
+# dumps_binary / loads_binary
+# ---------------------------------------------------------------------------
+
+
+def test_binary_round_trips(tree: ast.AST):
+    data = fast_walk.dumps_binary(tree)
+    assert isinstance(data, bytes)
+    rebuilt = fast_walk.loads_binary(data)
+    assert ast.dump(rebuilt, include_attributes=True) == ast.dump(tree, include_attributes=True)
+    compile(rebuilt, "<loads_binary>", "exec")
+    assert fast_walk.dumps_binary(rebuilt) == data
+
+
+@pytest.mark.parametrize("value", CONSTANTS, ids=repr)
+def test_binary_constant_values(value):
+    node = ast.Constant(value=value, lineno=2**80, col_offset=-3, end_lineno=None)
+    rebuilt = fast_walk.loads_binary(fast_walk.dumps_binary(node))
+    assert type(rebuilt.value) is type(value)
+    assert repr(rebuilt.value) == repr(value)
+    assert (rebuilt.lineno, rebuilt.col_offset, rebuilt.end_lineno) == (2**80, -3, None)
+    assert rebuilt.end_col_offset is None  # the class default
+
+
+def test_binary_keeps_missing_fields_missing():
+    with warnings.catch_warnings():
+        warnings.simplefilter("ignore", DeprecationWarning)
+        node = ast.Name(id="x")
+    del node.ctx
+    node.lineno = "not a position"
+    rebuilt = fast_walk.loads_binary(fast_walk.dumps_binary(node))
+    assert "ctx" not in rebuilt.__dict__
+    assert (rebuilt.id, rebuilt.lineno) == ("x", "not a position")
+
+
+def test_binary_shares_strings_and_field_less_nodes():
+    tree = ast.parse("spam = spam + spam\nspam(spam)")
+    data = fast_walk.dumps_binary(tree)
+    assert data.count(b"spam") == 1
+    rebuilt = fast_walk.loads_binary(data)
+    names = [n for n in ast.walk(rebuilt) if isinstance(n, ast.Name)]
+    assert len({id(n.id) for n in names}) == 1
+    assert len({id(n.ctx) for n in names if isinstance(n.ctx, ast.Load)}) == 1
+
+
+def test_binary_roots():
+    body = ast.parse("x = 1\ny = 2").body
+    rebuilt = fast_walk.loads_binary(fast_walk.dumps_binary(body))
+    assert [ast.dump(n) for n in rebuilt] == [ast.dump(n) for n in body]
+    assert fast_walk.loads_binary(fast_walk.dumps_binary([])) == []
+    with pytest.raises(TypeError, match="dumps_binary"):
+        fast_walk.dumps_binary("x = 1")
+
+
+def test_dumps_binary_rejects_what_it_cannot_restore():
+    class Custom(ast.Name):
+        pass
+
+    with pytest.raises(TypeError, match="only supports the stdlib ast classes, got .*Custom"):
+        fast_walk.dumps_binary(ast.Expr(value=Custom(id="x", ctx=ast.Load())))
+    with pytest.raises(TypeError, match="can't serialize objects of type object"):
+        fast_walk.dumps_binary(ast.Constant(value=object()))
+
+
+def test_loads_binary_rejects_stale_and_corrupt_data():
+    data = fast_walk.dumps_binary(ast.parse(SOURCES["functions"]))
+    major, minor = sys.version_info[:2]
+    stale = data[:5] + bytes([major, minor - 1]) + data[7:]
+    with pytest.raises(ValueError, match=rf"written by Python {major}\.{minor - 1}, but this is"):
+        fast_walk.loads_binary(stale)
+    with pytest.raises(ValueError, match="format version 99"):
+        fast_walk.loads_binary(data[:4] + b"\x63" + data[5:])
+    with pytest.raises(ValueError, match="bad magic number"):
+        fast_walk.loads_binary(pickle.dumps(ast.parse("x")))
+    with pytest.raises(ValueError, match="unexpected data after the tree"):
+        fast_walk.loads_binary(data + b"\x00")
+    # Every truncation is reported, never read past the end.
+    for end in range(len(data)):
+        with pytest.raises(ValueError, match="loads_binary"):
+            fast_walk.loads_binary(data[:end])
+
+
+def test_binary_deep_tree_raises_recursion_error():
+    node = ast.Constant(1)
+    for _ in range(100_000):
+        node = ast.UnaryOp(op=ast.USub(), operand=node)
+    with pytest.raises(RecursionError):
+        fast_walk.dumps_binary(node)
+    header = fast_walk.dumps_binary([])[:8]
+    with pytest.raises(RecursionError):
+        fast_walk.loads_binary(header + b"\x0c\x01" * 100_000 + b"\x00")
+
+
+# ---------------------------------------------------------------------------
+# dump
+# ---------------------------------------------------------------------------
+
+
+@pytest.mark.parametrize("annotate_fields", [True, False])
+@pytest.mark.parametrize("include_attributes", [False, True])
+@pytest.mark.parametrize("indent", [None, 0, 2, "\t", -1])
+def test_dump_matches_ast_dump(tree: ast.AST, annotate_fields, include_attributes, indent):
+    args = (tree, annotate_fields, include_attributes)
+    assert fast_walk.dump(*args, indent=indent) == ast.dump(*args, indent=indent)
+
+
+@pytest.mark.parametrize("show_empty", [False, True])
+def test_dump_show_empty(tree: ast.AST, show_empty: bool):
+    for annotate_fields in (True, False):
+        expected = ast.dump(tree, annotate_fields, show_empty=show_empty)
+        assert fast_walk.dump(tree, annotate_fields, show_empty=show_empty) == expected
+
+
+def _unset_ctx() -> ast.Name:
+    node = ast.Name(id="x")
+    del node.ctx
+    return node
+
+
+DUMP_EDGE_CASES = {
+    # Held-back `None`/`[]` values are written when a later positional
+    # argument needs them to keep its place.
+    "held_back": lambda: ast.arguments(
+        posonlyargs=[],
+        args=[],
+        vararg=None,
+        kwonlyargs=[],
+        kw_defaults=[],
+        kwarg=None,
+        defaults=[ast.Constant(1)],
+    ),
+    "none_is_a_value": lambda: ast.MatchSingleton(value=None),
+    "constant_none": lambda: ast.Constant(value=None, kind=None),
+    "unset_field": _unset_ctx,
+    "odd_values": lambda: ast.Constant(value=("é\ud800", b"\xff", 1j, ..., frozenset({1}))),
+    "odd_positions": lambda: ast.Name(id="x", ctx=ast.Load(), lineno="one", end_lineno=None),
+    "tuple_field": lambda: ast.Tuple(elts=(ast.Name(id="a"),), ctx=ast.Load()),
+    "generic": lambda: _generic({"a": [_generic({})], "b": None, "c": []}),
+}
+
+
+@pytest.mark.parametrize("name", DUMP_EDGE_CASES)
+def test_dump_edge_cases(name: str):
+    node = DUMP_EDGE_CASES[name]()
+    for annotate_fields, include_attributes, indent, show_empty in itertools.product(
+        [True, False], [False, True], [None, 3], [False, True]
+    ):
+        args = (node, annotate_fields, include_attributes)
+        kwargs = {"indent": indent, "show_empty": show_empty}
+        assert fast_walk.dump(*args, **kwargs) == ast.dump(*args, **kwargs)
+
+
+def test_dump_errors_match_ast_dump():
+    for bad in ("x = 1", [ast.Pass()]):
+        with pytest.raises(TypeError) as expected:
+            ast.dump(bad)
+        with pytest.raises(TypeError) as actual:
+            fast_walk.dump(bad)
+        assert str(actual.value) == str(expected.value)
+    with pytest.raises(TypeError):
+        fast_walk.dump(ast.Pass(), indent=1.5)
+
+
+# ---------------------------------------------------------------------------
+# to_dot
+# ---------------------------------------------------------------------------
+
+_DOT_VERTEX = re.compile(r'  n(\d+) \[label="((?:[^"\\]|\\.)*)"\];')
+_DOT_EDGE = re.compile(r'  n(\d+) -> n(\d+) \[label="((?:[^"\\]|\\.)*)"\];')
+
+
+def _read_dot(dot: str) -> tuple[list[list[str]], list[tuple[int, int, str]]]:
+    """Vertex labels (as lines, in id order) and edges of `to_dot` output."""
+    lines = dot.splitlines()
+    assert lines[:2] == ["digraph AST {", "  node [shape=box];"] and lines[-1] == "}"
+    vertices, edges = [], []
+    for line in lines[2:-1]:
+        if match := _DOT_VERTEX.fullmatch(line):
+            assert int(match[1]) == len(vertices)
+            label = match[2].replace("\\n", "\n").replace('\\"', '"').replace("\\\\", "\\")
+            vertices.append(label.split("\n"))
+        else:
+            match = _DOT_EDGE.fullmatch(line)
+            assert match, line
+            edges.append((int(match[1]), int(match[2]), match[3]))
+    return vertices, edges
+
+
+def _dot_reference(root: ast.AST) -> tuple[list[str], list[tuple[int, int, str]]]:
+    """Vertex types in `_fields` pre-order and labelled edges, from `ast`."""
+    types, edges = [], []
+    todo = [(root, None, "")]
+    while todo:
+        node, parent, label = todo.pop()
+        node_id = len(types)
+        types.append(type(node).__name__)
+        if parent is not None:
+            edges.append((parent, node_id, label))
+        children = []
+        for name, value in ast.iter_fields(node):
+            if isinstance(value, list):
+                children.extend(
+                    (item, node_id, f"{name}[{i}]")
+                    for i, item in enumerate(value)
+                    if isinstance(item, ast.AST)
+                )
+            elif isinstance(value, ast.AST):
+                children.append((value, node_id, name))
+        todo.extend(reversed(children))
+    return types, edges
+
+
+def test_to_dot_matches_ast_fields(tree: ast.AST):
+    vertices, edges = _read_dot(fast_walk.to_dot(tree))
+    types, expected_edges = _dot_reference(tree)
+    assert [label[0] for label in vertices] == types
+    assert edges == expected_edges
+
+
+def test_to_dot_labels_scalar_fields():
+    tree = ast.parse('global a, b\nf(x, "q\\"uote\\\\", None)\ndef g() -> None: pass')
+    vertices, _ = _read_dot(fast_walk.to_dot(tree))
+    labels = {"\n".join(label) for label in vertices}
+    assert "Global\nnames=['a', 'b']" in labels
+    assert "Name\nid='x'" in labels
+    assert "Constant\nvalue='q\"uote\\\\'" in labels
+    # `Constant.value` is shown even when None; unset optional fields
+    # (`kind`, `type_comment`) are not.
+    assert "Constant\nvalue=None" in labels
+    assert "FunctionDef\nname='g'" in labels
+    long = ast.Constant(value="x" * 100)
+    (label,), _ = _read_dot(fast_walk.to_dot(long))
+    assert label == ["Constant", "value='" + "x" * 36 + "..."]
+
+
+def test_to_dot_positions_and_options():
+    tree = ast.parse("x = 1\nyy")
+    vertices, _ = _read_dot(fast_walk.to_dot(tree, show_positions=True))
+    assert vertices[0] == ["Module"]
+    assert vertices[1][-1] == "1:0-1:5"
+    assert ["Name", "id='yy'", "2:0-2:2"] in vertices
+    assert ["Load"] in vertices
+    partial = ast.Name(id="x", ctx=ast.Load(), lineno=3, col_offset=1)
+    (label, _), _ = _read_dot(fast_walk.to_dot(partial, show_positions=True))
+    assert label[-1] == "3:1"
+
+    vertices, edges = _read_dot(fast_walk.to_dot(tree.body))
+    assert [v[0] for v in vertices].count("Assign") == 1 and len(edges) == len(vertices) - 2
+    with pytest.raises(fast_walk.TreeTooLarge):
+        fast_walk.to_dot(tree, max_nodes=3)
+    with pytest.raises(TypeError, match="to_dot"):
+        fast_walk.to_dot("x = 1")
+
+
+def test_to_dot_numbers_vertices_in_fields_order():
+    # Built out of `_fields` order: `walk_dfs` visits `right` first, the
+    # vertices follow `_fields`.
+    node = ast.BinOp(right=ast.Name(id="b", ctx=ast.Load()), op=ast.Add(), left=ast.Constant(1))
+    vertices, edges = _read_dot(fast_walk.to_dot(node))
+    assert [v[0] for v in vertices] == _dot_reference(node)[0]
+    assert [v[0] for v in vertices] == ["BinOp", "Constant", "Add", "Name", "Load"]
+    assert [type(n).__name__ for n in walk_dfs(node)][1] == "Name"
+
+
+def test_to_dot_rejects_cycles():
+    loop = ast.parse("if x:\n    pass").body[0]
+    loop.body.append(loop)
+    with pytest.raises(ValueError, match="cycle: If node .* through If.body\\[1\\]"):
+        fast_walk.to_dot(loop)
+    # Shared nodes are not cycles.
+    name = ast.Name(id="x", ctx=ast.Load())
+    shared = ast.BinOp(left=name, op=ast.Add(), right=name)
+    assert len(_read_dot(fast_walk.to_dot(shared))[0]) == 6
+
+
+# ---------------------------------------------------------------------------
+# to_sexpr
+# ---------------------------------------------------------------------------
+
+
+def _sexpr_reference(node: ast.AST, width: int, column: int = 0, closing: int = 0) -> str:
+    """Straightforward (quadratic) `to_sexpr`, laid out from `column` and
+    followed by `closing` `)`s on its last line."""
+    parts = []
+    for name, value in ast.iter_fields(node):
+        items = value if isinstance(value, list) else [value]
+        for item in items:
+            if isinstance(item, ast.expr_context):
+                continue
+            if isinstance(item, ast.AST):
+                parts.append(item)
+            elif item is None and not isinstance(value, list) and (
+                getattr(type(node), name, ...) is None
+            ):
+                pass
+            elif isinstance(item, str) and name != "value" and item.isascii() and (
+                item.isidentifier()
+            ):
+                parts.append(item)
+            else:
+                parts.append(repr(item))
+    if not parts:
+        return type(node).__name__
+
+    def flat(part):
+        return part if isinstance(part, str) else _sexpr_reference(part, sys.maxsize)
+
+    one_line = f"({type(node).__name__} {' '.join(map(flat, parts))})"
+    if column + len(one_line) + closing <= width:
+        return one_line
+    indent = column + 2
+    lines = [f"({type(node).__name__}"]
+    for i, part in enumerate(parts):
+        if not isinstance(part, str):
+            # The last child shares its line with this node's `)`.
+            part = _sexpr_reference(
+                part, width, indent, closing + 1 if i == len(parts) - 1 else 0
+            )
+        lines.append(" " * indent + part)
+    return "\n".join(lines) + ")"
+
+
+@pytest.mark.parametrize("width", [0, 20, 40, 100, sys.maxsize])
+def test_to_sexpr_matches_reference(tree: ast.AST, width: int):
+    assert fast_walk.to_sexpr(tree, width=width) == _sexpr_reference(tree, width)
+
+
+def test_to_sexpr_examples():
+    tree = ast.parse("print(1)")
+    assert fast_walk.to_sexpr(tree) == "(Module (Expr (Call (Name print) (Constant 1))))"
+    assert fast_walk.to_sexpr(tree.body[0].value) == "(Call (Name print) (Constant 1))"
+    assert fast_walk.to_sexpr(tree, width=38) == (
+        "(Module\n"
+        "  (Expr\n"
+        "    (Call (Name print) (Constant 1))))"
+    )
+    assert "\n    (Call\n" in fast_walk.to_sexpr(tree, width=37)
+    # Statements of a list are rendered one after another.
+    body = ast.parse("global a, b\nx = 'y' + None\npass").body
+    assert fast_walk.to_sexpr(body) == (
+        "(Global a b)\n"
+        "(Assign (Name x) (BinOp (Constant 'y') Add (Constant None)))\n"
+        "Pass"
+    )
+    # Strings that aren't ASCII identifiers are quoted.
+    alias = ast.alias(name="os.path", asname="café")
+    assert fast_walk.to_sexpr(alias) == "(alias 'os.path' 'café')"
+    assert fast_walk.to_sexpr([]) == ""
+
+
+def test_to_sexpr_counts_characters():
+    tree = ast.parse("f('éééééé')")
+    one_line = fast_walk.to_sexpr(tree, width=sys.maxsize)
+    assert len(one_line) < len(one_line.encode())
+    assert fast_walk.to_sexpr(tree, width=len(one_line)) == one_line
+    assert fast_walk.to_sexpr(tree, width=len(one_line) - 1) != one_line
+
+
+def test_to_sexpr_deep_and_errors():
+    source = "-" * 5000 + "x"
+    expected = "(Module (Expr " + "(UnaryOp USub " * 5000 + "(Name x)" + ")" * 5002
+    assert fast_walk.to_sexpr(ast.parse(source), width=sys.maxsize) == expected
+    with pytest.raises(TypeError, match="to_sexpr"):
+        fast_walk.to_sexpr("print(1)")
+    with pytest.raises(OverflowError):
+        fast_walk.to_sexpr(ast.parse("x"), width=-1)
+    with pytest.raises(fast_walk.TreeTooLarge):
+        fast_walk.to_sexpr(ast.parse(source), max_nodes=100)
+    assert fast_walk.to_sexpr(ast.parse("x"), max_nodes=3) == "(Module (Expr (Name x)))"
+
+    loop = ast.parse("if x:\n    pass").body[0]
+    loop.orelse.append(loop)
+    with pytest.raises(ValueError, match="cycle: If node .* through If.orelse\\[0\\]"):
+        fast_walk.to_sexpr(loop)
+    name = ast.Name(id="x", ctx=ast.Load())
+    shared = ast.BinOp(left=name, op=ast.Add(), right=name)
+    assert fast_walk.to_sexpr(shared) == "(BinOp (Name x) Add (Name x))"
+
+
+def test_to_sexpr_skips_contexts_by_type():
+    class Wrapper(ast.AST):
+        _fields = ("ctx", "where")
+
+    # A real child under the name `ctx` is kept; a context under any other
+    # name is dropped.
+    node = Wrapper(ctx=ast.Name(id="x", ctx=ast.Load()), where=ast.Store())
+    assert fast_walk.to_sexpr(node) == "(Wrapper (Name x))"
+    assert fast_walk.to_sexpr(node) == _sexpr_reference(node, 100)
+    assert fast_walk.to_sexpr(Wrapper(ctx=[ast.Del(), ast.Pass()])) == "(Wrapper Pass)"
+
+
+# ---------------------------------------------------------------------------
+# ast_equal
+# ---------------------------------------------------------------------------
+
+
+def test_ast_equal_ignores_positions(tree: ast.AST):
+    source = ast.unparse(tree)
+    shifted = ast.increment_lineno(ast.parse(source), 3)
+    assert fast_walk.ast_equal(ast.parse(source), shifted)
+    assert fast_walk.ast_equal(ast.parse(source), shifted, return_path=True) is None
+    if shifted.body:
+        assert not fast_walk.ast_equal(ast.parse(source), shifted, compare_attributes=True)
+        path = fast_walk.ast_equal(
+            ast.parse(source), shifted, compare_attributes=True, return_path=True
+        )
+        # Fields come before attributes, so this is the first node with a
+        # position in depth-first order.
+        assert path.startswith("$.body[0]") and path.endswith(".lineno")
+
+
+@pytest.mark.skipif(not hasattr(ast, "compare"), reason="ast.compare is new in 3.14")
+def test_ast_equal_matches_ast_compare(tree: ast.AST):
+    for other in SOURCES.values():
+        other = ast.parse(other)
+        for compare_attributes in (False, True):
+            assert fast_walk.ast_equal(
+                tree, other, compare_attributes=compare_attributes
+            ) == ast.compare(tree, other, compare_attributes=compare_attributes)
+
+
+@pytest.mark.parametrize(
+    ("a", "b", "path"),
+    [
+        ("x", "y", "$.body[0].value.id"),
+        ("f(1)", "f(True)", "$.body[0].value.args[0].value"),
+        ("f(1)", "f(1.0)", "$.body[0].value.args[0].value"),
+        ("f(1)", "f(1, 2)", "$.body[0].value.args"),
+        ("f(1, x)", "f(1, y)", "$.body[0].value.args[1].id"),
+        ("a + b", "a - b", "$.body[0].value.op"),
+        ("x = 1", "x: int = 1", "$.body[0]"),
+        ("x = 1\ny = 2", "x = 1\ny = 3", "$.body[1].value.value"),
+        ("import a as b", "import a", "$.body[0].names[0].asname"),
+        ("del x", "x", "$.body[0]"),
+        # `ctx` is a field like any other.
+        ("x = y", "y = x", "$.body[0].targets[0].id"),
+        ("[x] = y", "[x] = [y]", "$.body[0].value"),
+    ],
+)
+def test_ast_equal_reports_first_mismatch(a: str, b: str, path: str):
+    a, b = ast.parse(a), ast.parse(b)
+    assert not fast_walk.ast_equal(a, b)
+    assert fast_walk.ast_equal(a, b, return_path=True) == path
+    assert fast_walk.ast_equal(b, a, return_path=True) == path
+
+
+def test_ast_equal_value_semantics():
+    # Values compare with `==` once their types match.
+    assert fast_walk.ast_equal(ast.Constant(0.0), ast.Constant(-0.0))
+    assert fast_walk.ast_equal(ast.Constant((1, 2)), ast.Constant((1, 2)))
+    assert fast_walk.ast_equal(ast.Constant((1,)), ast.Constant((True,)))
+    assert not fast_walk.ast_equal(ast.Constant(b"x"), ast.Constant("x"))
+    nan = ast.Constant(math.nan)
+    assert not fast_walk.ast_equal(nan, nan)
+
+    # A field missing from both nodes is equal; from one, a difference.
+    a, b = ast.Name(id="x"), ast.Name(id="x")
+    del a.id
+    assert fast_walk.ast_equal(a, b, return_path=True) == "$.id"
+    del b.id
+    assert fast_walk.ast_equal(a, b)
+    assert fast_walk.ast_equal(ast.AST(), ast.AST())
+    assert not fast_walk.ast_equal(ast.AST(), ast.Pass())
+
+    a = ast.Name(id="x", lineno=1, col_offset=0)
+    b = ast.Name(id="x", lineno=1, col_offset=0)
+    assert fast_walk.ast_equal(a, b, compare_attributes=True)
+    b.col_offset = 1
+    assert fast_walk.ast_equal(a, b, compare_attributes=True, return_path=True) == "$.col_offset"
+    del a.col_offset
+    assert not fast_walk.ast_equal(a, b, compare_attributes=True)
+
+
+def test_ast_equal_roots():
+    a, b = ast.parse("x = 1\nf()"), ast.parse("x = 1\nf(a)")
+    assert fast_walk.ast_equal(a.body[:1], b.body[:1])
+    assert fast_walk.ast_equal(a.body, b.body, return_path=True) == "$[1].value.args"
+    assert fast_walk.ast_equal(a.body, b.body[:1], return_path=True) == "$"
+    assert fast_walk.ast_equal(a.body, b, return_path=True) == "$"
+    with pytest.raises(TypeError, match="ast_equal"):
+        fast_walk.ast_equal(a, "x = 1")
+    with pytest.raises(TypeError, match="ast_equal"):
+        fast_walk.ast_equal([1], [1])
+
+
+def test_ast_equal_deep_tree_raises_recursion_error():
+    source = "-" * 5000 + "x"
+    assert fast_walk.ast_equal(ast.parse(source), ast.parse(source))
+    a, b = ast.Constant(1), ast.Constant(1)
+    for _ in range(100_000):
+        a = ast.UnaryOp(op=ast.USub(), operand=a)
+        b = ast.UnaryOp(op=ast.USub(), operand=b)
+    with pytest.raises(RecursionError):
+        fast_walk.ast_equal(a, b)
+
+
+# ---------------------------------------------------------------------------
+# subtree_hashes
+# ---------------------------------------------------------------------------
+
+
+def test_subtree_hashes_parallel_walk_dfs(tree: ast.AST):
+    hashes = fast_walk.subtree_hashes(tree)
+    assert len(hashes) == len(walk_dfs(tree))
+    assert all(0 <= h < 2**64 for h in hashes)
+    shifted = ast.increment_lineno(ast.parse(ast.unparse(tree)), 7)
+    assert fast_walk.subtree_hashes(shifted) == fast_walk.subtree_hashes(
+        ast.parse(ast.unparse(tree))
+    )
+
+
+def test_subtree_hashes_group_equal_subtrees():
+    # Across all sources, two subtrees hash alike exactly when they dump
+    # alike (positions aside).
+    by_dump: dict[str, int] = {}
+    by_hash: dict[int, str] = {}
+    for source in SOURCES.values():
+        tree = ast.parse(source)
+        for node, h in zip(walk_dfs(tree), fast_walk.subtree_hashes(tree), strict=True):
+            text = ast.dump(node)
+            assert by_dump.setdefault(text, h) == h
+            assert by_hash.setdefault(h, text) == text
+    assert len(by_hash) > 500
+
+
+def test_subtree_hashes_values():
+    def same(a: ast.AST, b: ast.AST) -> bool:
+        return fast_walk.subtree_hashes(a) == fast_walk.subtree_hashes(b)
+
+    # As for `ast_equal`: values of the same type compare with `==`...
+    assert same(ast.Constant(0.0), ast.Constant(-0.0))
+    assert same(ast.Constant(2**100), ast.Constant(2**100))
+    assert not same(ast.Constant(1), ast.Constant(True))
+    assert not same(ast.Constant(1), ast.Constant(1.0))
+    assert not same(ast.Constant("x"), ast.Constant(b"x"))
+    assert not same(ast.Constant(None), ast.Constant(...))
+    # ... and inside tuples and frozensets, with `==` alone.
+    assert same(ast.Constant((1, 2.0)), ast.Constant((True, 2)))
+    assert not same(ast.Constant((1, 2)), ast.Constant((2, 1)))
+    assert same(ast.Constant(frozenset("abc")), ast.Constant(frozenset("cba")))
+    assert same(ast.Constant(frozenset({1})), ast.Constant(frozenset({1.0})))
+
+    # Context is structure: `x` loaded and `x` stored differ.
+    target, value = ast.parse("x = x").body[0].targets[0], ast.parse("x = x").body[0].value
+    assert not same(target, value)
+    a, b = ast.Name(id="x", ctx=ast.Load()), ast.Name(id="x", ctx=ast.Load())
+    del a.id
+    assert not same(a, b)
+
+    # Not Python's randomized `hash()`: the same on every run.
+    assert fast_walk.subtree_hashes(ast.parse("x").body[0].value)[0] == 14694984276848067386
+
+
+def test_subtree_hashes_follow_walk_dfs_for_out_of_order_fields():
+    # Built with keyword arguments out of `_fields` order, the node
+    # stores `right` first, and `walk_dfs` visits it first.
+    a = ast.Name(id="a", ctx=ast.Load())
+    b = ast.Name(id="b", ctx=ast.Load())
+    node = ast.BinOp(right=b, op=ast.Add(), left=a)
+    assert walk_dfs(node)[1] is b
+
+    hashes = fast_walk.subtree_hashes(node)
+    by_node = dict(zip(map(id, walk_dfs(node)), hashes, strict=True))
+    assert by_node[id(a)] == fast_walk.subtree_hashes(a)[0]
+    assert by_node[id(b)] == fast_walk.subtree_hashes(b)[0]
+    assert by_node[id(a)] != by_node[id(b)]
+    # Still the same hash as the node built in order.
+    parsed = ast.parse("a + b", mode="eval").body
+    assert hashes[0] == fast_walk.subtree_hashes(parsed)[0]
+
+
+def test_subtree_hashes_container_constants():
+    def same(a: object, b: object) -> bool:
+        return fast_walk.subtree_hashes(ast.Constant(a)) == fast_walk.subtree_hashes(
+            ast.Constant(b)
+        )
+
+    assert same(((1, [2]),), ((True, [2.0]),))
+    assert not same(([1, 2],), ([2, 1],))
+    assert same(({1, 2},), (frozenset({2, 1}),))
+    assert same(({"a": 1, "b": [2]},), ({"b": [2], "a": 1},))
+    assert not same(({"a": 1},), ({"a": 2},))
+    assert same({"a": 1}, {"a": 1})
+
+    # A bare `ast.AST()` is a (childless) node, not an identity hash.
+    assert fast_walk.subtree_hashes(
+        ast.Expr(value=ast.AST())
+    ) == fast_walk.subtree_hashes(ast.Expr(value=ast.AST()))
+
+    class Opaque:
+        pass
+
+    class Unhashable:
+        __hash__ = None
+
+    for value in (Opaque(), (1, Opaque()), Unhashable()):
+        with pytest.raises(TypeError, match="subtree_hashes.* can't hash"):
+            fast_walk.subtree_hashes(ast.Constant(value))
+    # `ast_equal` and `dump` take the same trees.
+    unhashable = ast.Constant(Unhashable())
+    assert fast_walk.ast_equal(unhashable, unhashable)
+    assert fast_walk.dump(unhashable) == ast.dump(unhashable)
+
+
+def test_subtree_hashes_roots():
+    tree = ast.parse("f(x)\ny = 1")
+    assert fast_walk.subtree_hashes(tree.body) == fast_walk.subtree_hashes(tree)[1:]
+    assert fast_walk.subtree_hashes([]) == []
+    with pytest.raises(TypeError, match="subtree_hashes"):
+        fast_walk.subtree_hashes("f(x)")
+
+    node = ast.Constant(1)
+    for _ in range(100_000):
+        node = ast.UnaryOp(op=ast.USub(), operand=node)
+    hashes = fast_walk.subtree_hashes(node)
+    assert len(hashes) == 200_001 and len(set(hashes)) == 100_002
@@ -138,3 +138,81 @@ def test_no_leak_growth(iterations):
     gc.collect()
     after = [sys.getrefcount(n) for n in sample]
     assert before == after
+
+
+def test_refcount_neutral_with_into():
+    """Refilling a reused list must release the previous contents: each
+    walk replaces every item, so after the last one the references held
+    are exactly those of a single result list."""
+    tree = ast.parse(SOURCE)
+    sample = list(ast.walk(tree))
+    out = []
+    gc.collect()
+    before = [sys.getrefcount(n) for n in sample]
+    fast_walk(tree, into=out)
+    once = [sys.getrefcount(n) for n in sample]
+    for _ in range(1000):
+        fast_walk(tree, into=out)
+    gc.collect()
+    assert [sys.getrefcount(n) for n in sample] == once
+    out.clear()
+    assert [sys.getrefcount(n) for n in sample] == before
+
+
+def test_refcount_neutral_with_walk_grouped():
+    """Each node is referenced once by its bucket, and dropping the dict
+    releases every reference, including those of non-AST roots that go
+    through the dict rather than the pre-sized buckets."""
+    from fast_walk import walk_grouped
+
+    tree = ast.parse(SOURCE)
+    stray = object()
+    sample = [*ast.walk(tree), stray]
+    gc.collect()
+    before = [sys.getrefcount(n) for n in sample]
+    for _ in range(1000):
+        walk_grouped([tree, stray], strict=False)
+    gc.collect()
+    assert [sys.getrefcount(n) for n in sample] == before
+    groups = walk_grouped([tree, stray], strict=False)
+    counts = Counter(id(n) for nodes in groups.values() for n in nodes)
+    during = [sys.getrefcount(n) for n in sample]
+    assert during == [b + counts[id(n)] for b, n in zip(before, sample)]
+
+
+def test_refcount_neutral_with_json_round_trip():
+    """`to_json` borrows field values out of instance dicts; `from_json`
+    must leave the classes it instantiates (and their field names) as
+    it found them."""
+    from fast_walk import from_json, to_json
+
+    tree = ast.parse(SOURCE)
+    sample = [*ast.walk(tree), *(type(n) for n in ast.walk(tree))]
+    from_json(to_json(tree))  # builds this thread's field table
+    gc.collect()
+    before = [sys.getrefcount(n) for n in sample]
+    for _ in range(200):
+        from_json(to_json(tree))
+    gc.collect()
+    assert [sys.getrefcount(n) for n in sample] == before
+
+
+def test_refcount_neutral_with_binary_round_trip():
+    """Shared strings and field-less nodes are only referenced by the
+    trees that use them."""
+    from fast_walk import dumps_binary, loads_binary
+
+    tree = ast.parse(SOURCE)
+    sample = [*ast.walk(tree), *(type(n) for n in ast.walk(tree))]
+    loads_binary(dumps_binary(tree))  # builds this thread's field table
+    gc.collect()
+    before = [sys.getrefcount(n) for n in sample]
+    for _ in range(200):
+        loads_binary(dumps_binary(tree))
+    gc.collect()
+    assert [sys.getrefcount(n) for n in sample] == before
+    rebuilt = loads_binary(dumps_binary(tree))
+    load = next(n.ctx for n in ast.walk(rebuilt) if isinstance(n, ast.Name))
+    uses = sum(getattr(n, "ctx", None) is load for n in ast.walk(rebuilt))
+    # The `load` variable and getrefcount's argument hold the other two.
+    assert sys.getrefcount(load) == uses + 2